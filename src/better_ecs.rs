@@ -15,6 +15,22 @@ use std::sync::Mutex;
 
 use super::util::RefCellTryReplaceExt;
 
+/// Set several components on one entity, in order, as if by calling
+/// `Ecs::set` for each. Evaluates to a `Result` holding a tuple of the new
+/// `ComponentId`s, or the first error hit:
+///
+/// ```ignore
+/// let (transform_id, physics_id) = set_components!(ecs, entity, transform, physics)?;
+/// ```
+macro_rules! set_components {
+    ($ecs:expr, $entity:expr, $($component:expr),+ $(,)*) => {{
+        let entity = $entity;
+        (|| -> Result<_, $crate::better_ecs::EcsError> {
+            Ok(($($ecs.set(entity, $component)?,)+))
+        })()
+    }};
+}
+
 lazy_static! {
     static ref NEXT_ECS_ID: Mutex<IdNumber> = Mutex::new(0);
 }
@@ -22,7 +38,7 @@ lazy_static! {
 type IdNumber = u64;
 type ComponentMap = HashMap<TypeId, ComponentId>;
 
-#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
 pub struct EcsId(IdNumber);
 
 impl EcsId {
@@ -39,16 +55,42 @@ impl EcsId {
 ///
 /// There is a static guarantee that no two entities in the same Ecs will
 /// ever share an EntityId, including deleted entities.
-#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
 pub struct EntityId(EcsId, IdNumber);
 
+impl EntityId {
+    /// This entity's number within its Ecs. It never changes, and no other
+    /// entity in the same Ecs will have it, so it works as a key for side
+    /// tables kept outside the Ecs.
+    pub fn index(&self) -> u64 {
+        self.1
+    }
+
+    /// Which Ecs this entity belongs to.
+    pub fn ecs_index(&self) -> u64 {
+        (self.0).0
+    }
+}
+
 /// A unique ID tag for a component in an Ecs system.
 ///
 /// There is a static guarantee that no two components in the same Ecs will
 /// ever share a ComponentId, including deleted or replaced components.
-#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
 pub struct ComponentId(EcsId, IdNumber);
 
+impl ComponentId {
+    /// This component's number within its Ecs; see `EntityId::index`.
+    pub fn index(&self) -> u64 {
+        self.1
+    }
+
+    /// Which Ecs this component belongs to.
+    pub fn ecs_index(&self) -> u64 {
+        (self.0).0
+    }
+}
+
 /// A convenient way to store ComponentIds with type information.
 #[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
 pub struct ComponentRef<T: Component> {
@@ -61,8 +103,29 @@ impl<T: Component> ComponentRef<T> {
         ComponentRef { id, p: PhantomData }
     }
 
+    /// Like `ComponentRef::new`, but checks that `id` really is a `T`, so a
+    /// mis-wired ref fails here instead of at its first borrow.
+    pub fn new_checked(id: ComponentId, ecs: &Ecs) -> Result<Self, EcsError> {
+        if ecs.component_is_type::<T>(id)? {
+            Ok(Self::new(id))
+        } else {
+            Err(EcsError::ComponentTypeMismatch(id))
+        }
+    }
+
     pub fn from_entity(id: EntityId, ecs: &Ecs) -> Result<Self, EcsError> {
-        Ok(Self::new(ecs.lookup_component::<T>(id)?))
+        Self::new_checked(ecs.lookup_component::<T>(id)?, ecs)
+    }
+
+    /// The entity the referenced component is attached to.
+    pub fn entity(&self, ecs: &Ecs) -> Result<EntityId, EcsError> {
+        ecs.get_parent(self.id)
+    }
+
+    /// Returns true if the referenced component still exists and has the
+    /// right type. Refs go stale when their component is removed.
+    pub fn is_valid(&self, ecs: &Ecs) -> bool {
+        ecs.component_is_type::<T>(self.id).unwrap_or(false)
     }
 
     pub fn borrow<'a>(&self, ecs: &'a Ecs) -> Result<Ref<'a, T>, EcsError> {
@@ -104,15 +167,67 @@ pub enum EcsError {
     /// The requested component cannot be borrowed right now.
     BorrowError(ComponentId),
 
+    /// The requested component is mutably borrowed, so it can't be read
+    /// right now; it should be free again next frame. See `Ecs::read`.
+    ComponentBusy(ComponentId),
+
+    /// Every `EntityId` or `ComponentId` this Ecs can hand out has been
+    /// used, so nothing more can be created.
+    IdExhausted,
+
     /// Some internal error occurred; this indicates that there is a bug
     /// in the library.
     InternalError(&'static str, Option<Box<EcsError>>),
 }
 
+/// A change to the Ecs, recorded when the event log is turned on with
+/// `Ecs::enable_event_log`.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub enum EcsEvent {
+    Spawned(EntityId),
+    Removed(EntityId),
+    ComponentSet(EntityId, TypeId),
+    ComponentRemoved(EntityId, TypeId),
+}
+
+/// How much is in an `Ecs`, from `Ecs::stats`. Watching these counts
+/// is an easy way to spot things that never get despawned.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct EcsStats {
+    pub entities: usize,
+    pub components: usize,
+    /// How many components there are of each type.
+    pub by_type: HashMap<TypeId, usize>,
+    /// Readable names for the types in `by_type`; see `Ecs::type_name_of`.
+    pub type_names: HashMap<TypeId, &'static str>,
+}
+
+impl EcsStats {
+    /// The totals, then one line per component type with its count,
+    /// sorted by name. Module paths are left off the names.
+    pub fn report(&self) -> Vec<String> {
+        let mut counts: Vec<(&str, usize)> = self
+            .by_type
+            .iter()
+            .map(|(type_id, &count)| {
+                let name = self.type_names.get(type_id).cloned().unwrap_or("?");
+                (name.rsplit("::").next().unwrap(), count)
+            })
+            .collect();
+        counts.sort();
+
+        let totals = format!("Entities: {}  Components: {}", self.entities, self.components);
+        std::iter::once(totals)
+            .chain(counts.iter().map(|(name, count)| format!("{}: {}", name, count)))
+            .collect()
+    }
+}
+
 struct ComponentEntry {
     pub refbox: RefCell<Box<Any>>,
     pub parent: EntityId,
     pub type_id: TypeId,
+    pub type_name: &'static str,
 }
 
 impl ComponentEntry {
@@ -121,6 +236,7 @@ impl ComponentEntry {
             refbox: RefCell::new(Box::new(component)),
             parent,
             type_id: TypeId::of::<T>(),
+            type_name: std::any::type_name::<T>(),
         }
     }
 }
@@ -140,6 +256,11 @@ impl<'a> EntityBuilder<'a> {
         }
     }
 
+    // The ref handed to the `with*` closures, checked against the Ecs.
+    fn component_ref<A: Component>(&self) -> Result<ComponentRef<A>, EcsError> {
+        ComponentRef::from_entity(self.entity, self.parent)
+    }
+
     pub fn with<T: Component>(mut self, component: T) -> Self {
         if self.err.is_some() {
             return self;
@@ -159,12 +280,12 @@ impl<'a> EntityBuilder<'a> {
             return self;
         }
 
-        let arg1 = match self.parent.lookup_component::<A>(self.entity) {
+        let arg1 = match self.component_ref::<A>() {
             Ok(a) => a,
             Err(e) => {self.err = Some(e); return self;}
         };
 
-        let component = f(arg1.into());
+        let component = f(arg1);
 
         if let Err(e) = self.parent.set(self.entity, component) {
             self.err = Some(e);
@@ -180,16 +301,16 @@ impl<'a> EntityBuilder<'a> {
             return self;
         }
 
-        let arg1 = match self.parent.lookup_component::<A>(self.entity) {
+        let arg1 = match self.component_ref::<A>() {
             Ok(a) => a,
             Err(e) => {self.err = Some(e); return self;}
         };
-        let arg2 = match self.parent.lookup_component::<B>(self.entity) {
+        let arg2 = match self.component_ref::<B>() {
             Ok(a) => a,
             Err(e) => {self.err = Some(e); return self;}
         };
 
-        let component = f(arg1.into(), arg2.into());
+        let component = f(arg1, arg2);
 
         if let Err(e) = self.parent.set(self.entity, component) {
             self.err = Some(e);
@@ -205,20 +326,20 @@ impl<'a> EntityBuilder<'a> {
             return self;
         }
 
-        let arg1 = match self.parent.lookup_component::<A>(self.entity) {
+        let arg1 = match self.component_ref::<A>() {
             Ok(a) => a,
             Err(e) => {self.err = Some(e); return self;}
         };
-        let arg2 = match self.parent.lookup_component::<B>(self.entity) {
+        let arg2 = match self.component_ref::<B>() {
             Ok(a) => a,
             Err(e) => {self.err = Some(e); return self;}
         };
-        let arg3 = match self.parent.lookup_component::<C>(self.entity) {
+        let arg3 = match self.component_ref::<C>() {
             Ok(a) => a,
             Err(e) => {self.err = Some(e); return self;}
         };
 
-        let component = f(arg1.into(), arg2.into(), arg3.into());
+        let component = f(arg1, arg2, arg3);
 
         if let Err(e) = self.parent.set(self.entity, component) {
             self.err = Some(e);
@@ -227,9 +348,14 @@ impl<'a> EntityBuilder<'a> {
         self
     }
 
+    /// Finish the entity. If any component couldn't be added, the entity
+    /// is removed again, so nothing half-built is left behind.
     pub fn build(self) -> Result<EntityId, EcsError> {
         match self.err {
-            Some(e) => Err(e),
+            Some(e) => {
+                let _ = self.parent.remove_entity(self.entity);
+                Err(e)
+            }
             None => Ok(self.entity)
         }
     }
@@ -253,6 +379,51 @@ pub struct Ecs {
 
     /// A map of component Ids to component data.
     components: HashMap<ComponentId, ComponentEntry>,
+
+    /// Every change made, if anyone asked for them. `None` means nothing
+    /// is recorded.
+    event_log: Option<Vec<EcsEvent>>,
+
+    /// Display names for component types, from `Ecs::register_named`.
+    type_names: HashMap<TypeId, &'static str>,
+}
+
+/// Changes to an `Ecs` queued up while it's being iterated, to be applied
+/// afterward with `CommandBuffer::apply`. `Ecs::scoped` hands one out and
+/// applies it for you.
+///
+/// An empty buffer doesn't allocate; each queued command costs one box.
+#[derive(Default)]
+pub struct CommandBuffer {
+    commands: Vec<Box<dyn FnOnce(&mut Ecs)>>,
+}
+
+impl CommandBuffer {
+    pub fn new() -> Self {
+        CommandBuffer {
+            commands: Vec::new(),
+        }
+    }
+
+    /// Queue up `command` to be run on the Ecs later.
+    pub fn push<F: FnOnce(&mut Ecs) + 'static>(&mut self, command: F) {
+        self.commands.push(Box::new(command));
+    }
+
+    pub fn len(&self) -> usize {
+        self.commands.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+
+    /// Run every queued command on `ecs`, in the order they were pushed.
+    pub fn apply(self, ecs: &mut Ecs) {
+        for command in self.commands {
+            command(ecs);
+        }
+    }
 }
 
 /// This is a trait for all components. It's auto-implemented for everything.
@@ -269,9 +440,24 @@ impl Ecs {
             next_component_id: 0,
             entities: HashMap::new(),
             components: HashMap::new(),
+            event_log: None,
+            type_names: HashMap::new(),
         }
     }
 
+    /// Run `f` with read access to the Ecs and a `CommandBuffer` to queue
+    /// changes in, then apply the changes once `f` is done. This is how
+    /// systems spawn things while iterating over the world.
+    pub fn scoped<R, F>(&mut self, f: F) -> R
+    where
+        F: FnOnce(&Ecs, &mut CommandBuffer) -> R,
+    {
+        let mut commands = CommandBuffer::new();
+        let result = f(self, &mut commands);
+        commands.apply(self);
+        result
+    }
+
     /// Create a new Ecs with no allocations. Useful when paired with the
     /// Ecs::merge function.
     pub fn empty() -> Self {
@@ -281,6 +467,31 @@ impl Ecs {
             next_component_id: 0,
             entities: HashMap::with_capacity(0),
             components: HashMap::with_capacity(0),
+            event_log: None,
+            type_names: HashMap::with_capacity(0),
+        }
+    }
+
+    /// Start recording every entity and component added or removed, to be
+    /// collected with `Ecs::drain_events`.
+    pub fn enable_event_log(&mut self) {
+        if self.event_log.is_none() {
+            self.event_log = Some(Vec::new());
+        }
+    }
+
+    /// Take all the events recorded since the last call. Returns nothing if
+    /// the event log isn't enabled.
+    pub fn drain_events(&mut self) -> Vec<EcsEvent> {
+        match &mut self.event_log {
+            Some(log) => log.drain(..).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    fn log_event(&mut self, event: EcsEvent) {
+        if let Some(log) = &mut self.event_log {
+            log.push(event);
         }
     }
 
@@ -299,6 +510,7 @@ impl Ecs {
 
         self.components.extend(other.components);
         self.entities.extend(other.entities);
+        self.type_names.extend(other.type_names);
     }
 
     fn create_entity_id(&mut self) -> Option<EntityId> {
@@ -313,19 +525,20 @@ impl Ecs {
         Some(ComponentId(self.ecs_id, new_id_number))
     }
 
-    /// Create an entity, or return `None` if no more `EntityIds` can be
-    /// created.
+    /// Create an entity, or return `EcsError::IdExhausted` if no more
+    /// `EntityIds` can be created.
     ///
     /// The ECS can go through `n^64-1` unique ID's before panicking,
     /// so this practically never fails.
     ///
     /// This is the non-panicking variant of `create_entity`.
-    pub fn try_create_entity(&mut self) -> Option<EntityId> {
-        let new_id = self.create_entity_id()?;
+    pub fn try_create_entity(&mut self) -> Result<EntityId, EcsError> {
+        let new_id = self.create_entity_id().ok_or(EcsError::IdExhausted)?;
 
         self.entities.insert(new_id, HashMap::new());
+        self.log_event(EcsEvent::Spawned(new_id));
 
-        Some(new_id)
+        Ok(new_id)
     }
 
     /// Create an entity.
@@ -351,15 +564,24 @@ impl Ecs {
         EntityBuilder::new(self, id)
     }
 
+    /// The non-panicking variant of `build_entity`.
+    pub fn try_build_entity(&mut self) -> Result<EntityBuilder, EcsError> {
+        let id = self.try_create_entity()?;
+        Ok(EntityBuilder::new(self, id))
+    }
+
     // Note: Does not touch the entities map.
-    fn create_component<T: Component>(&mut self, component: T, parent: EntityId) -> ComponentId {
-        // TODO: Unwrap
-        let new_id = self.create_component_id().unwrap();
+    fn create_component<T: Component>(
+        &mut self,
+        component: T,
+        parent: EntityId,
+    ) -> Result<ComponentId, EcsError> {
+        let new_id = self.create_component_id().ok_or(EcsError::IdExhausted)?;
 
         self.components
             .insert(new_id, ComponentEntry::new(component, parent));
 
-        new_id
+        Ok(new_id)
     }
 
     /// Delete an entity and all components attached to it. Returns an error
@@ -377,15 +599,53 @@ impl Ecs {
                 None,
             ))?;
         }
+        self.log_event(EcsEvent::Removed(entity));
 
         Ok(())
     }
 
+    /// Remove the component of type `T` from `entity`, returning it. Any
+    /// `ComponentRef`s to it become invalid.
+    pub fn unset<T: Component>(&mut self, entity: EntityId) -> Result<T, EcsError> {
+        let id = self.lookup_component::<T>(entity)?;
+        if let Some(components) = self.entities.get_mut(&entity) {
+            components.remove(&TypeId::of::<T>());
+        }
+        let entry = self
+            .components
+            .remove(&id)
+            .ok_or(EcsError::ComponentNotFound(id))?;
+        self.log_event(EcsEvent::ComponentRemoved(entity, entry.type_id));
+        entry
+            .refbox
+            .into_inner()
+            .downcast::<T>()
+            .map(|boxed_t| *boxed_t)
+            .map_err(|_| EcsError::ComponentTypeMismatch(id))
+    }
+
+    /// Remove every `T` from every entity, returning them along with the
+    /// entities they came from, in ascending component id order. The
+    /// entities themselves, and their other components, are kept.
+    pub fn drain_components<T: Component>(&mut self) -> Vec<(EntityId, T)> {
+        let owners: Vec<EntityId> = self
+            .components_sorted::<T>()
+            .map(|id| self.get_parent(id).unwrap())
+            .collect();
+        owners
+            .into_iter()
+            .map(|entity| (entity, self.unset::<T>(entity).unwrap()))
+            .collect()
+    }
+
     // Note: Does not touch the entities map.
     // Inverse of create_component.
     fn remove_component(&mut self, component: ComponentId) -> Result<(), EcsError> {
         match self.components.remove(&component) {
-            Some(_) => Ok(()),
+            Some(entry) => {
+                self.log_event(EcsEvent::ComponentRemoved(entry.parent, entry.type_id));
+                Ok(())
+            }
             None => Err(EcsError::ComponentNotFound(component)),
         }
     }
@@ -429,6 +689,20 @@ impl Ecs {
             .and_then(|opt| opt.ok_or(EcsError::ComponentTypeNotFound(entity)))
     }
 
+    /// Give components of type `T` a name for debug displays, in place of the
+    /// full Rust type name.
+    pub fn register_named<T: Component>(&mut self, name: &'static str) {
+        self.type_names.insert(TypeId::of::<T>(), name);
+    }
+
+    /// A readable name for the type of `component`: the one given to
+    /// `Ecs::register_named`, or else the Rust type name. Returns `None` if
+    /// the component doesn't exist.
+    pub fn type_name_of(&self, component: ComponentId) -> Option<&str> {
+        let entry = self.components.get(&component)?;
+        Some(self.type_names.get(&entry.type_id).unwrap_or(&entry.type_name))
+    }
+
     /// Returns the ID of the entity that `component` is attached to.
     pub fn get_parent(&self, component: ComponentId) -> Result<EntityId, EcsError> {
         self.components
@@ -437,6 +711,12 @@ impl Ecs {
             .map(|data| data.parent)
     }
 
+    /// The parents of all of `ids`, in order. Returns an error for the first
+    /// component that doesn't exist, rather than a partial list.
+    pub fn parents_of(&self, ids: &[ComponentId]) -> Result<Vec<EntityId>, EcsError> {
+        ids.iter().map(|&id| self.get_parent(id)).collect()
+    }
+
     /// Returns true if `component` is attached to `entity`. Returns an error if
     /// the component or the entity don't exist.
     pub fn is_component_attached(
@@ -470,7 +750,7 @@ impl Ecs {
         entity: EntityId,
         component: T,
     ) -> Result<ComponentId, EcsError> {
-        let component_id = self.create_component(component, entity);
+        let component_id = self.create_component(component, entity)?;
         let entity_components = self
             .entities
             .get_mut(&entity)
@@ -482,6 +762,7 @@ impl Ecs {
                 EcsError::InternalError("Failed to remove old component.", Some(Box::new(e)))
             })?;
         }
+        self.log_event(EcsEvent::ComponentSet(entity, TypeId::of::<T>()));
 
         Ok(component_id)
     }
@@ -532,6 +813,73 @@ impl Ecs {
             })
     }
 
+    /// Replace the `T` component on `entity` with `f` applied to it. The value is moved
+    /// out and back in, so `T` doesn't need to be `Clone`.
+    ///
+    /// Returns an error if `entity` has no `T`, or if it is currently borrowed.
+    pub fn map_component<T: Component>(
+        &self,
+        entity: EntityId,
+        f: impl FnOnce(T) -> T,
+    ) -> Result<(), EcsError> {
+        let component_id = self
+            .has_component::<T>(entity)?
+            .ok_or(EcsError::ComponentTypeNotFound(entity))?;
+        let cell = self.get_refcell(component_id)?;
+
+        // Park a placeholder in the cell while `f` runs.
+        let boxed_any = cell
+            .try_replace(Box::new(()))
+            .map_err(|_| EcsError::BorrowError(component_id))?;
+        let component = boxed_any.downcast::<T>().map(|boxed_t| *boxed_t).map_err(|_| {
+            panic!("Typecheck succeded and then failed! Ecs left in inconsistent state!")
+        })?;
+
+        let mapped: Box<Any> = Box::new(f(component));
+        cell.replace(mapped);
+        Ok(())
+    }
+
+    /// Exchange the `T` components of `a` and `b` in place. The components keep
+    /// their `ComponentId`s, so any `ComponentRef`s now see the other value.
+    ///
+    /// Returns an error if either entity has no `T`, or if either component is
+    /// currently borrowed.
+    pub fn swap_components<T: Component>(&self, a: EntityId, b: EntityId) -> Result<(), EcsError> {
+        let a_id = self.has_component::<T>(a)?.ok_or(EcsError::ComponentTypeNotFound(a))?;
+        let b_id = self.has_component::<T>(b)?.ok_or(EcsError::ComponentTypeNotFound(b))?;
+        if a_id == b_id {
+            return Ok(());
+        }
+
+        let a_cell = self.get_refcell(a_id)?;
+        let b_cell = self.get_refcell(b_id)?;
+        a_cell.try_swap(b_cell).map_err(|_| {
+            // Report whichever one is actually borrowed.
+            if a_cell.try_borrow_mut().is_err() {
+                EcsError::BorrowError(a_id)
+            } else {
+                EcsError::BorrowError(b_id)
+            }
+        })
+    }
+
+    /// Set the component on `entity` for type `T` to `component`, like inserting
+    /// into a map: if `entity` already has one, it's replaced in place (keeping
+    /// its `ComponentId`) and returned; otherwise a new one is created and this
+    /// returns `None`.
+    pub fn insert<T: Component>(
+        &mut self,
+        entity: EntityId,
+        component: T,
+    ) -> Result<Option<T>, EcsError> {
+        if let Some(component_id) = self.has_component::<T>(entity)? {
+            self.replace_by_id_unchecked(component_id, component).map(Some)
+        } else {
+            self.set(entity, component).map(|_| None)
+        }
+    }
+
     /// Set the component on `entity` for type `T` to `component`. If `entity` doesn't
     /// already have a component of type `T`, this creates a new one.
     ///
@@ -561,6 +909,16 @@ impl Ecs {
         }
     }
 
+    /// Like `Ecs::set`, but returns a `ComponentRef`, ready to hand to
+    /// another component that refers to this one.
+    pub fn set_ref<T: Component>(
+        &mut self,
+        entity: EntityId,
+        component: T,
+    ) -> Result<ComponentRef<T>, EcsError> {
+        self.set(entity, component).map(ComponentRef::new)
+    }
+
     fn get_refcell(&self, id: ComponentId) -> Result<&RefCell<Box<Any>>, EcsError> {
         self.components
             .get(&id)
@@ -575,6 +933,21 @@ impl Ecs {
         self.borrow(entity).map(|c: Ref<T>| c.clone())
     }
 
+    /// Get a snapshot of the specified component, for code that only
+    /// looks, like rendering and AI.
+    ///
+    /// This works like `Ecs::get`: the component is cloned under an
+    /// immutable borrow, which is dropped before returning. Use it only
+    /// when nothing is being changed. The difference is that a mutably
+    /// borrowed component gives `ComponentBusy`, not `BorrowError`, so
+    /// the caller can just try again next frame.
+    pub fn read<T: Component + Clone>(&self, entity: EntityId) -> Result<T, EcsError> {
+        self.get(entity).map_err(|e| match e {
+            EcsError::BorrowError(id) => EcsError::ComponentBusy(id),
+            e => e,
+        })
+    }
+
     /// Get an immutable borrow of the specified component.
     /// Borrows of components are independent of each other.
     ///
@@ -591,6 +964,23 @@ impl Ecs {
         Ref::new(refbox).ok_or(EcsError::ComponentTypeMismatch(id))
     }
 
+    /// Like `Ecs::get`, for when the component may legitimately be missing.
+    ///
+    /// Returns `None` if the entity doesn't exist or has no `T`. A borrow
+    /// conflict also gives `None`; that should only happen through a bug,
+    /// so use `Ecs::get` where it needs telling apart.
+    pub fn try_get<T: Component + Clone>(&self, entity: EntityId) -> Option<T> {
+        self.get(entity).ok()
+    }
+
+    /// Like `Ecs::borrow`, for when the component may legitimately be missing.
+    ///
+    /// Returns `None` if the entity doesn't exist or has no `T`, and also if
+    /// the component is mutably borrowed; see `Ecs::try_get`.
+    pub fn try_borrow<T: Component>(&self, entity: EntityId) -> Option<Ref<T>> {
+        self.borrow(entity).ok()
+    }
+
     /// Get a mutable borrow of the specified component.
     /// Borrows of components are independant of each other.
     ///
@@ -607,6 +997,20 @@ impl Ecs {
         RefMut::new(refbox).ok_or(EcsError::ComponentTypeMismatch(id))
     }
 
+    /// Mutably borrow the specified component just long enough to run `f`
+    /// on it, and return whatever `f` returns. This keeps the borrow short
+    /// and explicit, instead of holding a `RefMut` across unrelated calls.
+    ///
+    /// Returns an error if a mutable or immutable borrow of this component already exists.
+    pub fn with_mut<T: Component, R>(
+        &self,
+        entity: EntityId,
+        f: impl FnOnce(&mut T) -> R,
+    ) -> Result<R, EcsError> {
+        let mut component = self.borrow_mut::<T>(entity)?;
+        Ok(f(&mut component))
+    }
+
     /// Get a copy of the specified component.
     ///
     /// Returns an error if a mutable borrow of this component already exists.
@@ -663,6 +1067,25 @@ impl Ecs {
         dest.extend(self.entities.keys().cloned());
     }
 
+    /// Iterator over all entity IDs. The lazy version of `Ecs::collect`.
+    pub fn iter_entities<'a>(&'a self) -> impl Iterator<Item = EntityId> + 'a {
+        self.entities.keys().cloned()
+    }
+
+    /// Remove every entity whose `T` component matches `pred`, returning
+    /// the entities removed.
+    pub fn despawn_if<T: Component, F: Fn(&T) -> bool>(&mut self, pred: F) -> Vec<EntityId> {
+        let doomed: Vec<EntityId> = self
+            .components_ref::<T>()
+            .filter(|(_, component)| pred(component))
+            .map(|(id, _)| self.get_parent(id).unwrap())
+            .collect();
+        for &entity in &doomed {
+            self.remove_entity(entity).unwrap();
+        }
+        doomed
+    }
+
     /// Iterator over all components of a specific type.
     pub fn components<'a, T: Component>(&'a self) -> impl Iterator<Item = ComponentId> + 'a {
         self.components
@@ -673,6 +1096,18 @@ impl Ecs {
             .map(|(&id, _)| id)
     }
 
+    /// Like `Ecs::components`, but in ascending id order, so the order is the same
+    /// from run to run (`components` follows the `HashMap`, which isn't). Use it
+    /// where results depend on order, e.g. for replays.
+    ///
+    /// This collects and sorts all the ids up front, so it costs an allocation and
+    /// O(n log n) time per call, where `components` is lazy and free.
+    pub fn components_sorted<T: Component>(&self) -> impl Iterator<Item = ComponentId> {
+        let mut ids: Vec<ComponentId> = self.components::<T>().collect();
+        ids.sort();
+        ids.into_iter()
+    }
+
     /// Iterator over all components of a specific type, yielding a reference to each.
     /// Note that this will panic when advancing the iterator if the next component is
     /// currently mutably borrowed.
@@ -687,11 +1122,77 @@ impl Ecs {
         IterMut::new(self.components::<T>(), self)
     }
 
+    /// Iterator over every entity with both an `A` and a `B`, yielding a reference
+    /// to each. Walks whichever type is rarer. Like `Ecs::components_ref`, this
+    /// will panic when advancing the iterator if the next component is currently
+    /// mutably borrowed.
+    pub fn components2_ref<'a, A: Component, B: Component>(
+        &'a self,
+    ) -> impl Iterator<Item = (EntityId, Ref<'a, A>, Ref<'a, B>)> + 'a {
+        let entities = if self.count_components::<A>() <= self.count_components::<B>() {
+            self.entities_with::<A>()
+        } else {
+            self.entities_with::<B>()
+        };
+        entities.into_iter().filter_map(move |entity| {
+            let a = self.has_component::<A>(entity).ok()??;
+            let b = self.has_component::<B>(entity).ok()??;
+            Some((entity, self.borrow_by_id(a).unwrap(), self.borrow_by_id(b).unwrap()))
+        })
+    }
+
+    /// The first entity whose `T` component satisfies `pred`, if any.
+    /// Stops searching as soon as one is found.
+    pub fn find_entity<T: Component>(&self, pred: impl Fn(&T) -> bool) -> Option<EntityId> {
+        self.components_ref::<T>()
+            .find(|(_, component)| pred(component))
+            .map(|(id, _)| self.get_parent(id).unwrap())
+    }
+
+    /// Call `f` with every entity that has a `T` component, along with the
+    /// component. Only immutable borrows are taken, so this is fine to use
+    /// while other components are borrowed; any `T` that is currently
+    /// mutably borrowed is skipped rather than causing a panic.
+    pub fn for_each_with<T: Component>(&self, mut f: impl FnMut(EntityId, &T)) {
+        for id in self.components::<T>() {
+            if let (Ok(entity), Ok(component)) = (self.get_parent(id), self.borrow_by_id::<T>(id)) {
+                f(entity, &component);
+            }
+        }
+    }
+
     pub fn entities_with<T: Component>(&self) -> Vec<EntityId> {
         self.components::<T>()
             .map(|id| self.get_parent(id).unwrap())
             .collect()
     }
+
+    /// Whether any entity has a `T` at all. Stops at the first one found,
+    /// so it's cheaper than `count_components` when that's all you need.
+    pub fn has_any<T: Component>(&self) -> bool {
+        self.components::<T>().next().is_some()
+    }
+
+    /// Count the number of components of a specific type.
+    pub fn count_components<T: Component>(&self) -> usize {
+        self.components::<T>().count()
+    }
+
+    /// Count everything in the Ecs, with the components broken down by
+    /// type.
+    pub fn stats(&self) -> EcsStats {
+        let mut stats = EcsStats {
+            entities: self.entities.len(),
+            components: self.components.len(),
+            ..EcsStats::default()
+        };
+        for entry in self.components.values() {
+            *stats.by_type.entry(entry.type_id).or_insert(0) += 1;
+            let name = self.type_names.get(&entry.type_id).unwrap_or(&entry.type_name);
+            stats.type_names.insert(entry.type_id, name);
+        }
+        stats
+    }
 }
 
 pub struct Iter<'a, I: Iterator<Item = ComponentId>, T: Component> {
@@ -825,6 +1326,11 @@ mod test {
     #[derive(Copy, Clone, PartialEq, Debug)]
     struct Velocity(Vector2);
 
+    #[derive(Copy, Clone, PartialEq, Debug)]
+    struct Health {
+        health: f32,
+    }
+
     fn update_position(pos: &Position, vel: &Velocity) -> Position {
         Position(pos.0 + vel.0)
     }
@@ -898,4 +1404,411 @@ mod test {
         assert!(error.is_err());
         println!("{:?}", *borrow);
     }
+
+    #[test]
+    fn test_nested_with_mut() {
+        let mut ecs = Ecs::new();
+        let a = ecs.create_entity();
+        let _ = ecs.set(a, Position(Vector2::new(0.0, 0.0)));
+        let _ = ecs.set(a, Velocity(Vector2::new(1.0, 2.0)));
+
+        let nested = ecs.with_mut(a, |pos: &mut Position| {
+            ecs.with_mut(a, |vel: &mut Velocity| pos.0 += vel.0)
+        });
+        assert!(nested == Ok(Ok(())));
+        assert!(ecs.get::<Position>(a) == Ok(Position(Vector2::new(1.0, 2.0))));
+
+        let id = ecs.lookup_component::<Position>(a).unwrap();
+        let same = ecs.with_mut(a, |_: &mut Position| {
+            ecs.with_mut(a, |_: &mut Position| ())
+        });
+        assert!(same == Ok(Err(EcsError::BorrowError(id))));
+    }
+
+    #[test]
+    fn test_find_entity() {
+        let mut ecs = Ecs::new();
+        let healthy = ecs.create_entity();
+        let hurt = ecs.create_entity();
+        let _ = ecs.set(healthy, Health { health: 1.0 });
+        let _ = ecs.set(hurt, Health { health: 0.2 });
+
+        assert_eq!(ecs.find_entity(|h: &Health| h.health < 0.5), Some(hurt));
+        assert_eq!(ecs.find_entity(|h: &Health| h.health > 2.0), None);
+    }
+
+    #[test]
+    fn test_for_each_with() {
+        let mut ecs = Ecs::new();
+        for &health in [1.0, 2.5, 0.5].iter() {
+            let entity = ecs.create_entity();
+            let _ = ecs.set(entity, Health { health });
+        }
+        let unhealthy = ecs.create_entity();
+        let _ = ecs.set(unhealthy, Position(Vector2::new(0.0, 0.0)));
+
+        let mut total = 0.0;
+        let mut count = 0;
+        ecs.for_each_with(|_, h: &Health| {
+            total += h.health;
+            count += 1;
+        });
+
+        assert_eq!(total, 4.0);
+        assert_eq!(count, 3);
+    }
+
+    #[test]
+    fn test_swap_components() {
+        let mut ecs = Ecs::new();
+        let a = ecs.create_entity();
+        let b = ecs.create_entity();
+        let empty = ecs.create_entity();
+        let a_id = ecs.set(a, Position(Vector2::new(1.0, 2.0))).unwrap();
+        let _ = ecs.set(b, Position(Vector2::new(-3.0, 4.0)));
+
+        assert_eq!(ecs.swap_components::<Position>(a, b), Ok(()));
+        assert_eq!(ecs.get::<Position>(a), Ok(Position(Vector2::new(-3.0, 4.0))));
+        assert_eq!(ecs.get::<Position>(b), Ok(Position(Vector2::new(1.0, 2.0))));
+
+        assert_eq!(
+            ecs.swap_components::<Position>(a, empty),
+            Err(EcsError::ComponentTypeNotFound(empty))
+        );
+        let _held = ecs.borrow::<Position>(a).unwrap();
+        assert_eq!(ecs.swap_components::<Position>(a, b), Err(EcsError::BorrowError(a_id)));
+    }
+
+    #[test]
+    fn test_map_component() {
+        let mut ecs = Ecs::new();
+        let entity = ecs.create_entity();
+        let _ = ecs.set(entity, Health { health: 3.0 });
+
+        let halve = |h: Health| Health { health: h.health / 2.0 };
+        assert_eq!(ecs.map_component(entity, halve), Ok(()));
+        assert_eq!(ecs.get::<Health>(entity), Ok(Health { health: 1.5 }));
+
+        let empty = ecs.create_entity();
+        assert_eq!(
+            ecs.map_component(empty, halve),
+            Err(EcsError::ComponentTypeNotFound(empty))
+        );
+    }
+
+    #[test]
+    fn test_event_log() {
+        let mut ecs = Ecs::new();
+        let _ = ecs.create_entity();
+        assert_eq!(ecs.drain_events(), vec![]);
+
+        ecs.enable_event_log();
+        let entity = ecs.create_entity();
+        ecs.remove_entity(entity).unwrap();
+
+        assert_eq!(
+            ecs.drain_events(),
+            vec![EcsEvent::Spawned(entity), EcsEvent::Removed(entity)]
+        );
+        assert_eq!(ecs.drain_events(), vec![]);
+    }
+
+    #[test]
+    fn test_try_get() {
+        let mut ecs = Ecs::new();
+        let entity = ecs.create_entity();
+        let _ = ecs.set(entity, Health { health: 2.0 });
+        let removed = ecs.create_entity();
+        ecs.remove_entity(removed).unwrap();
+
+        assert_eq!(ecs.try_get::<Health>(entity), Some(Health { health: 2.0 }));
+        assert_eq!(ecs.try_borrow::<Health>(entity).map(|h| h.health), Some(2.0));
+
+        assert_eq!(ecs.try_get::<Position>(entity), None);
+        assert!(ecs.try_borrow::<Position>(entity).is_none());
+
+        assert_eq!(ecs.try_get::<Health>(removed), None);
+        assert!(ecs.try_borrow::<Health>(removed).is_none());
+    }
+
+    #[test]
+    fn test_checked_component_ref() {
+        let mut ecs = Ecs::new();
+        let entity = ecs.create_entity();
+        let position_id = ecs.set(entity, Position(Vector2::new(0.0, 0.0))).unwrap();
+
+        assert!(ComponentRef::<Position>::new_checked(position_id, &ecs).is_ok());
+        assert_eq!(
+            ComponentRef::<Velocity>::new_checked(position_id, &ecs),
+            Err(EcsError::ComponentTypeMismatch(position_id))
+        );
+    }
+
+    #[test]
+    fn test_iter_entities_matches_collect() {
+        let mut ecs = Ecs::new();
+        for _ in 0..5 {
+            ecs.create_entity();
+        }
+        let removed = ecs.create_entity();
+        ecs.remove_entity(removed).unwrap();
+
+        let mut collected = Vec::new();
+        ecs.collect(&mut collected);
+        collected.sort();
+        let mut iterated: Vec<EntityId> = ecs.iter_entities().collect();
+        iterated.sort();
+
+        assert_eq!(iterated, collected);
+        assert_eq!(iterated.len(), 5);
+    }
+
+    #[test]
+    fn test_components2_ref_joins() {
+        let mut ecs = Ecs::new();
+        let both = ecs.create_entity();
+        let _ = ecs.set(both, Position(Vector2::new(1.0, 0.0)));
+        let _ = ecs.set(both, Velocity(Vector2::new(0.0, 1.0)));
+        for _ in 0..3 {
+            let position_only = ecs.create_entity();
+            let _ = ecs.set(position_only, Position(Vector2::new(0.0, 0.0)));
+        }
+        let velocity_only = ecs.create_entity();
+        let _ = ecs.set(velocity_only, Velocity(Vector2::new(0.0, 0.0)));
+
+        let joined: Vec<_> = ecs
+            .components2_ref::<Position, Velocity>()
+            .map(|(entity, pos, vel)| (entity, *pos, *vel))
+            .collect();
+
+        assert_eq!(
+            joined,
+            vec![(both, Position(Vector2::new(1.0, 0.0)), Velocity(Vector2::new(0.0, 1.0)))]
+        );
+    }
+
+    #[test]
+    fn test_parents_of() {
+        let mut ecs = Ecs::new();
+        let a = ecs.create_entity();
+        let b = ecs.create_entity();
+        let a_pos = ecs.set(a, Position(Vector2::new(0.0, 0.0))).unwrap();
+        let b_pos = ecs.set(b, Position(Vector2::new(0.0, 0.0))).unwrap();
+        let b_vel = ecs.set(b, Velocity(Vector2::new(0.0, 0.0))).unwrap();
+
+        assert_eq!(ecs.parents_of(&[b_vel, a_pos, b_pos]), Ok(vec![b, a, b]));
+
+        ecs.unset::<Velocity>(b).unwrap();
+        assert_eq!(
+            ecs.parents_of(&[a_pos, b_vel]),
+            Err(EcsError::ComponentNotFound(b_vel))
+        );
+    }
+
+    #[test]
+    fn test_type_name_of() {
+        let mut ecs = Ecs::new();
+        ecs.register_named::<Position>("Position");
+        let entity = ecs.create_entity();
+        let pos = ecs.set(entity, Position(Vector2::new(0.0, 0.0))).unwrap();
+        let vel = ecs.set(entity, Velocity(Vector2::new(0.0, 0.0))).unwrap();
+
+        assert_eq!(ecs.type_name_of(pos), Some("Position"));
+        assert_eq!(ecs.type_name_of(vel), Some(std::any::type_name::<Velocity>()));
+
+        ecs.remove_entity(entity).unwrap();
+        assert_eq!(ecs.type_name_of(pos), None);
+    }
+
+    #[test]
+    fn test_components_sorted() {
+        let mut ecs = Ecs::new();
+        let entities: Vec<EntityId> = (0..20).map(|_| ecs.create_entity()).collect();
+        // Add them out of order, with other components mixed in.
+        for &entity in entities.iter().rev().step_by(2).chain(entities.iter().step_by(2)) {
+            let _ = ecs.set(entity, Position(Vector2::new(0.0, 0.0)));
+            let _ = ecs.set(entity, Velocity(Vector2::new(0.0, 0.0)));
+        }
+
+        let ids: Vec<ComponentId> = ecs.components_sorted::<Position>().collect();
+
+        assert_eq!(ids.len(), 20);
+        assert!(ids.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn test_scoped_applies_queued_spawns() {
+        let mut ecs = Ecs::new();
+
+        let seen_inside = ecs.scoped(|system, commands| {
+            commands.push(|ecs| {
+                let entity = ecs.create_entity();
+                ecs.set(entity, Health { health: 10.0 }).unwrap();
+            });
+            system.count_components::<Health>()
+        });
+
+        assert_eq!(seen_inside, 0);
+        assert_eq!(ecs.count_components::<Health>(), 1);
+    }
+
+    #[test]
+    fn test_set_components_macro() {
+        let mut ecs = Ecs::new();
+        let entity = ecs.create_entity();
+        let position = Position(Vector2::new(1.0, 2.0));
+        let velocity = Velocity(Vector2::new(3.0, 4.0));
+
+        let (position_id, velocity_id, health_id) =
+            set_components!(ecs, entity, position, velocity, Health { health: 5.0 }).unwrap();
+
+        assert_eq!(*ecs.borrow_by_id::<Position>(position_id).unwrap(), position);
+        assert_eq!(*ecs.borrow_by_id::<Velocity>(velocity_id).unwrap(), velocity);
+        assert_eq!(ecs.borrow_by_id::<Health>(health_id).unwrap().health, 5.0);
+        assert_eq!(ecs.lookup_component::<Health>(entity).unwrap(), health_id);
+    }
+
+    #[test]
+    fn test_id_exhaustion_is_an_error() {
+        let mut ecs = Ecs::new();
+        let entity = ecs.create_entity();
+
+        ecs.next_component_id = IdNumber::max_value();
+        let result = ecs.set(entity, Health { health: 1.0 });
+        assert_eq!(result.unwrap_err(), EcsError::IdExhausted);
+        assert_eq!(ecs.has_component::<Health>(entity).unwrap(), None);
+
+        ecs.next_entity_id = IdNumber::max_value();
+        assert_eq!(ecs.try_create_entity().unwrap_err(), EcsError::IdExhausted);
+        assert_eq!(ecs.try_build_entity().err(), Some(EcsError::IdExhausted));
+    }
+
+    #[test]
+    fn test_failed_build_leaves_nothing_behind() {
+        let mut ecs = Ecs::new();
+        let result = ecs
+            .build_entity()
+            .with(Position(Vector2::new(1.0, 2.0)))
+            .with1(|health: ComponentRef<Health>| Velocity(Vector2::new(0.0, 0.0)))
+            .build();
+
+        assert!(result.is_err());
+        assert_eq!(ecs.iter_entities().count(), 0);
+        assert!(!ecs.has_any::<Position>());
+    }
+
+    #[test]
+    fn test_insert_returns_old_value() {
+        let mut ecs = Ecs::new();
+        let entity = ecs.create_entity();
+
+        assert_eq!(ecs.insert(entity, Health { health: 1.0 }).unwrap(), None);
+        let id = ecs.lookup_component::<Health>(entity).unwrap();
+
+        let old = ecs.insert(entity, Health { health: 2.0 }).unwrap();
+        assert_eq!(old, Some(Health { health: 1.0 }));
+        assert_eq!(ecs.get::<Health>(entity).unwrap().health, 2.0);
+        assert_eq!(ecs.lookup_component::<Health>(entity).unwrap(), id);
+    }
+
+    #[test]
+    fn test_read_reports_busy_component() {
+        let mut ecs = Ecs::new();
+        let entity = ecs.create_entity();
+        let id = ecs.set(entity, Health { health: 3.0 }).unwrap();
+
+        assert_eq!(ecs.read::<Health>(entity), Ok(Health { health: 3.0 }));
+
+        let borrow = ecs.borrow_mut::<Health>(entity).unwrap();
+        assert_eq!(ecs.read::<Health>(entity), Err(EcsError::ComponentBusy(id)));
+        drop(borrow);
+
+        assert_eq!(ecs.read::<Health>(entity), Ok(Health { health: 3.0 }));
+    }
+
+    #[test]
+    fn test_entity_indices_are_distinct() {
+        let mut ecs = Ecs::new();
+        let a = ecs.create_entity();
+        let b = ecs.create_entity();
+        let other = Ecs::new().create_entity();
+
+        assert_ne!(a.index(), b.index());
+        assert_eq!(a.ecs_index(), b.ecs_index());
+        assert_ne!(a.ecs_index(), other.ecs_index());
+    }
+
+    #[test]
+    fn test_has_any() {
+        let mut ecs = Ecs::new();
+        let entity = ecs.create_entity();
+        ecs.set(entity, Position(Vector2::new(0.0, 0.0))).unwrap();
+
+        assert!(ecs.has_any::<Position>());
+        assert!(!ecs.has_any::<Velocity>());
+
+        ecs.remove_entity(entity).unwrap();
+        assert!(!ecs.has_any::<Position>());
+    }
+
+    #[test]
+    fn test_drain_components() {
+        let mut ecs = Ecs::new();
+        let a = ecs.create_entity();
+        let b = ecs.create_entity();
+        ecs.set(a, Position(Vector2::new(1.0, 0.0))).unwrap();
+        ecs.set(b, Position(Vector2::new(2.0, 0.0))).unwrap();
+        ecs.set(b, Velocity(Vector2::new(3.0, 0.0))).unwrap();
+
+        let drained = ecs.drain_components::<Position>();
+        assert_eq!(
+            drained,
+            vec![(a, Position(Vector2::new(1.0, 0.0))), (b, Position(Vector2::new(2.0, 0.0)))]
+        );
+
+        assert!(!ecs.has_any::<Position>());
+        assert_eq!(ecs.lookup_component::<Position>(b), Err(EcsError::ComponentTypeNotFound(b)));
+        assert!(ecs.has_entity(a));
+        assert_eq!(ecs.get::<Velocity>(b), Ok(Velocity(Vector2::new(3.0, 0.0))));
+    }
+
+    #[test]
+    fn test_stats_counts_by_type() {
+        let mut ecs = Ecs::new();
+        ecs.register_named::<Health>("Hit points");
+        let a = ecs.create_entity();
+        let b = ecs.create_entity();
+        ecs.create_entity();
+        ecs.set(a, Position(Vector2::new(1.0, 0.0))).unwrap();
+        ecs.set(a, Velocity(Vector2::new(0.0, 1.0))).unwrap();
+        ecs.set(b, Position(Vector2::new(2.0, 0.0))).unwrap();
+        ecs.set(b, Health { health: 3.0 }).unwrap();
+
+        let stats = ecs.stats();
+        assert_eq!(stats.entities, 3);
+        assert_eq!(stats.components, 4);
+        assert_eq!(stats.by_type.len(), 3);
+        assert_eq!(stats.by_type[&TypeId::of::<Position>()], 2);
+        assert_eq!(stats.by_type[&TypeId::of::<Velocity>()], 1);
+        assert_eq!(stats.by_type[&TypeId::of::<Health>()], 1);
+        assert_eq!(
+            stats.report(),
+            vec![
+                "Entities: 3  Components: 4",
+                "Hit points: 1",
+                "Position: 2",
+                "Velocity: 1"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_set_ref() {
+        let mut ecs = Ecs::new();
+        let entity = ecs.create_entity();
+
+        let health = ecs.set_ref(entity, Health { health: 4.0 }).unwrap();
+        assert_eq!(*health.borrow(&ecs).unwrap(), Health { health: 4.0 });
+        assert_eq!(health.entity(&ecs), Ok(entity));
+    }
 }
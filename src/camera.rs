@@ -0,0 +1,249 @@
+use ggez::graphics::{Point2, Vector2};
+
+use super::vec::wrapped_delta;
+use super::{screen_to_world_coords, world_to_screen_coords};
+
+/// How quickly the camera catches up with its target. Each second it
+/// closes this fraction of the gap (capped at all of it per step).
+pub const CAMERA_FOLLOW_RATE: f32 = 5.0;
+
+/// The furthest the camera shakes, in pixels, at full trauma.
+pub const MAX_SHAKE_OFFSET: f32 = 12.0;
+/// Trauma lost per second.
+pub const TRAUMA_DECAY: f32 = 1.0;
+
+/// How far the camera can zoom out and in.
+pub const MIN_ZOOM: f32 = 0.5;
+pub const MAX_ZOOM: f32 = 2.0;
+/// How much one press of the zoom keys zooms by.
+pub const ZOOM_STEP: f32 = 1.25;
+
+/// How far outside the view, in world units, something may be and still
+/// get drawn, so big sprites don't pop in at the edges.
+pub const VIEW_MARGIN: f32 = 64.0;
+
+/// *********************************************************************
+/// The view into the world. The camera's `center` is drawn in the
+/// middle of the screen, and everything in the world is scaled by
+/// `zoom`. The HUD is drawn in screen space and ignores the camera.
+///
+/// The playing field repeats in every direction when it wraps, so near
+/// a seam the camera sees past it into the other side of the field.
+/// `wrapped_copies` gives everywhere a thing shows up in view.
+///
+/// Hits add "trauma" to the camera, from 0 to 1, which shakes the view
+/// by an amount that grows with the square of the trauma. Trauma wears
+/// off over time.
+/// **********************************************************************
+#[derive(Debug, Clone, PartialEq)]
+pub struct Camera {
+    pub center: Point2,
+    pub zoom: f32,
+
+    pub trauma: f32,
+    // Drives the shake noise.
+    shake_time: f32,
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Camera {
+            center: Point2::origin(),
+            zoom: 1.0,
+            trauma: 0.0,
+            shake_time: 0.0,
+        }
+    }
+}
+
+/// Smooth-ish noise in [-1, 1]. Different `seed`s give unrelated curves.
+fn shake_noise(time: f32, seed: f32) -> f32 {
+    ((time * 31.0 + seed).sin() + (time * 53.0 + seed * 2.0).sin() * 0.5) / 1.5
+}
+
+impl Camera {
+    /// Ease the camera toward `target`. `field` is the size of the
+    /// wrapping playing field; when the target wraps to the other side
+    /// the camera jumps with it rather than sweeping across the world.
+    pub fn follow(&mut self, target: Point2, field: (f32, f32), dt: f32) {
+        let offset = target - self.center;
+        if offset.x.abs() > field.0 / 2.0 || offset.y.abs() > field.1 / 2.0 {
+            self.center = target;
+            return;
+        }
+        let t = (CAMERA_FOLLOW_RATE * dt).min(1.0);
+        self.center += offset * t;
+    }
+
+    /// Zoom in (`factor` above 1) or out, within `MIN_ZOOM` and `MAX_ZOOM`.
+    pub fn zoom_by(&mut self, factor: f32) {
+        self.zoom = (self.zoom * factor).max(MIN_ZOOM).min(MAX_ZOOM);
+    }
+
+    pub fn add_trauma(&mut self, amount: f32) {
+        self.trauma = (self.trauma + amount).min(1.0);
+    }
+
+    /// Let the trauma wear off.
+    pub fn update(&mut self, dt: f32) {
+        self.trauma = (self.trauma - TRAUMA_DECAY * dt).max(0.0);
+        self.shake_time += dt;
+    }
+
+    /// How far the view is shaken right now, in world units.
+    pub fn shake_offset(&self) -> Vector2 {
+        let shake = self.trauma * self.trauma * MAX_SHAKE_OFFSET;
+        Vector2::new(
+            shake * shake_noise(self.shake_time, 0.0),
+            shake * shake_noise(self.shake_time, 10.0),
+        )
+    }
+
+    /// Like `world_to_screen_coords`, but seen through the camera.
+    pub fn world_to_screen(&self, screen_width: u32, screen_height: u32, point: Point2) -> Point2 {
+        let center = self.center + self.shake_offset();
+        let relative = Point2::origin() + (point - center) * self.zoom;
+        world_to_screen_coords(screen_width, screen_height, relative)
+    }
+
+    /// Every position at which something at `pos` shows up in view (give
+    /// or take `VIEW_MARGIN`), on a field of size `field` that repeats in
+    /// every direction. The list is empty if it's nowhere in view. A
+    /// field that doesn't wrap has an infinite size (see
+    /// `WrapMode::collision_field`), and then `pos` itself is all there is.
+    pub fn wrapped_copies(
+        &self,
+        pos: Point2,
+        field: (f32, f32),
+        screen: (u32, u32),
+    ) -> Vec<Point2> {
+        let half_w = screen.0 as f32 / 2.0 / self.zoom + VIEW_MARGIN;
+        let half_h = screen.1 as f32 / 2.0 / self.zoom + VIEW_MARGIN;
+        // The whole numbers of fields to shift by along one axis to land
+        // within `half` of the camera.
+        let shifts = |pos: f32, center: f32, half: f32, size: f32| {
+            if !size.is_finite() {
+                return 0..=0;
+            }
+            let lo = ((center - half - pos) / size).ceil() as i32;
+            let hi = ((center + half - pos) / size).floor() as i32;
+            lo..=hi
+        };
+
+        let mut copies = Vec::new();
+        for i in shifts(pos.x, self.center.x, half_w, field.0) {
+            for j in shifts(pos.y, self.center.y, half_h, field.1) {
+                // Careful not to multiply an infinite field by zero.
+                let shift = |n: i32, size: f32| if n == 0 { 0.0 } else { n as f32 * size };
+                copies.push(pos + Vector2::new(shift(i, field.0), shift(j, field.1)));
+            }
+        }
+        copies
+    }
+
+    /// The copy of `pos` on a repeating field of size `field` that's
+    /// closest to the camera.
+    pub fn nearest_copy(&self, pos: Point2, field: (f32, f32)) -> Point2 {
+        self.center + wrapped_delta(pos, self.center, field.0, field.1)
+    }
+
+    /// The inverse of `world_to_screen`, e.g. to find what the mouse is
+    /// pointing at.
+    pub fn screen_to_world(&self, screen_width: u32, screen_height: u32, point: Point2) -> Point2 {
+        let center = self.center + self.shake_offset();
+        let relative = screen_to_world_coords(screen_width, screen_height, point);
+        center + (relative - Point2::origin()) / self.zoom
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_followed_player_draws_at_screen_center() {
+        let screen_center = Point2::new(320.0, 240.0);
+        let positions = [
+            Point2::origin(),
+            Point2::new(150.0, -80.0),
+            Point2::new(-300.0, 200.0),
+        ];
+        for &player_pos in positions.iter() {
+            for &zoom in [1.0, 2.0].iter() {
+                let camera = Camera {
+                    center: player_pos,
+                    zoom,
+                    ..Camera::default()
+                };
+                assert_eq!(camera.world_to_screen(640, 480, player_pos), screen_center);
+            }
+        }
+    }
+
+    #[test]
+    fn test_wrapped_copies_show_across_the_seam() {
+        let field = (640.0, 480.0);
+        // Looking at the right edge of the field.
+        let camera = Camera {
+            center: Point2::new(320.0, 0.0),
+            ..Camera::default()
+        };
+        let near_left_edge = Point2::new(-300.0, 0.0);
+        assert_eq!(
+            camera.wrapped_copies(near_left_edge, field, (640, 480)),
+            vec![Point2::new(340.0, 0.0)]
+        );
+        // The middle of the field is in view, and nothing past it.
+        let middle = Point2::new(100.0, 0.0);
+        assert_eq!(camera.wrapped_copies(middle, field, (640, 480)), vec![middle]);
+
+        // A field that doesn't wrap shows everything once, where it is.
+        let infinite = (std::f32::INFINITY, std::f32::INFINITY);
+        let copies = camera.wrapped_copies(near_left_edge, infinite, (640, 480));
+        assert_eq!(copies, vec![near_left_edge]);
+        assert_eq!(camera.nearest_copy(near_left_edge, field), Point2::new(340.0, 0.0));
+    }
+
+    #[test]
+    fn test_zoom_stays_in_range() {
+        let mut camera = Camera::default();
+        for _ in 0..10 {
+            camera.zoom_by(ZOOM_STEP);
+        }
+        assert_eq!(camera.zoom, MAX_ZOOM);
+        for _ in 0..20 {
+            camera.zoom_by(1.0 / ZOOM_STEP);
+        }
+        assert_eq!(camera.zoom, MIN_ZOOM);
+    }
+
+    #[test]
+    fn test_trauma_decays() {
+        let mut camera = Camera::default();
+        camera.add_trauma(1.0);
+
+        let dt = 0.25;
+        let steps = (1.0 / (TRAUMA_DECAY * dt)) as i32;
+        for _ in 0..steps - 1 {
+            camera.update(dt);
+            assert!(camera.trauma > 0.0);
+        }
+        camera.update(dt);
+
+        assert_eq!(camera.trauma, 0.0);
+        assert_eq!(camera.shake_offset(), Vector2::new(0.0, 0.0));
+    }
+
+    #[test]
+    fn test_shake_scales_with_trauma_squared() {
+        let mut camera = Camera::default();
+        camera.update(0.1);
+        camera.trauma = 1.0;
+        let full = camera.shake_offset();
+        camera.trauma = 0.5;
+        let half = camera.shake_offset();
+
+        assert!(full.norm() > 0.0);
+        assert!((half.norm() - full.norm() * 0.25).abs() < 1e-5);
+    }
+}
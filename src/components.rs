@@ -2,23 +2,159 @@ use ggez::graphics::{self, Point2, Vector2};
 use ggez::nalgebra as na;
 use ggez::{Context, GameResult};
 
-use super::better_ecs::{ComponentRef, Ecs};
-use super::prefabs::create_shot;
-use super::vec::vec_from_angle;
-use super::world_to_screen_coords;
-use super::{Assets, InputState};
-use super::{MAX_PHYSICS_VEL, SHOT_SPEED};
+use super::better_ecs::{CommandBuffer, ComponentRef, Ecs, EcsError, EntityId};
+use super::camera::Camera;
+use super::config::GameConfig;
+use super::simulation::Score;
+use super::prefabs::{create_shot, PLAYER_BBOX};
+use super::vec::{reflect, rotate_toward, vec_from_angle, wrapped_delta};
+use super::{Assets, InputState, MAX_PHYSICS_VEL};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum ActorType {
     Player,
     Rock,
+    Boss,
     Shot,
 }
 
+impl ActorType {
+    /// Bosses reuse the rock image (scaled up by their transform), so
+    /// they're tinted red to stand out.
+    pub fn sprite_color(&self) -> Option<graphics::Color> {
+        match self {
+            ActorType::Boss => Some(graphics::Color::new(1.0, 0.4, 0.4, 1.0)),
+            _ => None,
+        }
+    }
+
+    /// The RGBA color of the stand-in sprite for this kind of actor,
+    /// used when its image is missing.
+    pub fn placeholder_color(&self) -> (u8, u8, u8, u8) {
+        match self {
+            ActorType::Player => (80, 220, 80, 255),
+            ActorType::Rock => (150, 150, 150, 255),
+            ActorType::Boss => (220, 80, 80, 255),
+            ActorType::Shot => (240, 240, 80, 255),
+        }
+    }
+}
+
+/// The ways the player's gun can fire. Power-ups swap between these.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WeaponKind {
+    /// One shot per press.
+    Single,
+    /// A fan of `SPREAD_SHOT_COUNT` shots per press.
+    Spread,
+    /// Fires for as long as the button is held, faster than usual.
+    Rapid,
+    /// One shot per press, fired on release: a normal shot for a tap, or
+    /// a bigger one if it was held until the charge reached `MIN_CHARGE`.
+    Charged,
+}
+
+#[derive(Debug, Clone)]
+pub struct Weapon {
+    pub kind: WeaponKind,
+
+    // Builds up with each shot and cools off over time, from 0 to MAX_HEAT.
+    pub heat: f32,
+    // Set when the heat hits MAX_HEAT, and cleared once it cools to
+    // HEAT_RESUME. Nothing fires in between.
+    pub overheated: bool,
+}
+
+// Shots in a spread, and the angle between neighbouring ones, in radians.
+pub const SPREAD_SHOT_COUNT: usize = 3;
+pub const SPREAD_ANGLE: f32 = 0.2;
+// Rapid fire's cooldown, as a fraction of the normal one.
+pub const RAPID_COOLDOWN_FACTOR: f32 = 0.3;
+// Rapid fire's damage per shot, as a fraction of a normal shot's.
+pub const RAPID_DAMAGE_FACTOR: f32 = 0.5;
+
+// Heat added per shot, and lost per second.
+pub const HEAT_PER_SHOT: f32 = 0.15;
+pub const HEAT_COOL_RATE: f32 = 0.4;
+// An overheated weapon locks at MAX_HEAT and fires again below HEAT_RESUME.
+pub const MAX_HEAT: f32 = 1.0;
+pub const HEAT_RESUME: f32 = 0.5;
+
+impl Weapon {
+    pub fn new(kind: WeaponKind) -> Weapon {
+        Weapon {
+            kind,
+            heat: 0.0,
+            overheated: false,
+        }
+    }
+
+    pub fn add_heat(&mut self) {
+        self.heat = (self.heat + HEAT_PER_SHOT).min(MAX_HEAT);
+        if self.heat >= MAX_HEAT {
+            self.overheated = true;
+        }
+    }
+
+    pub fn cool(&mut self, dt: f32) {
+        self.heat = (self.heat - HEAT_COOL_RATE * dt).max(0.0);
+        if self.heat < HEAT_RESUME {
+            self.overheated = false;
+        }
+    }
+
+    /// How hot the weapon is, from 0 to 1.
+    pub fn heat_fraction(&self) -> f32 {
+        self.heat / MAX_HEAT
+    }
+
+    /// Seconds between shots, given the player's normal `shot_time`.
+    pub fn cooldown(&self, shot_time: f32) -> f32 {
+        match self.kind {
+            WeaponKind::Rapid => shot_time * RAPID_COOLDOWN_FACTOR,
+            _ => shot_time,
+        }
+    }
+
+    /// How many shots each press fires, and the arc they're spread
+    /// across, in radians; see `fire_spread`.
+    pub fn spread(&self) -> (usize, f32) {
+        match self.kind {
+            WeaponKind::Spread => {
+                (SPREAD_SHOT_COUNT, SPREAD_ANGLE * (SPREAD_SHOT_COUNT - 1) as f32)
+            }
+            _ => (1, 0.0),
+        }
+    }
+
+    /// Damage of each shot, relative to a normal one.
+    pub fn damage_factor(&self) -> f32 {
+        match self.kind {
+            WeaponKind::Rapid => RAPID_DAMAGE_FACTOR,
+            _ => 1.0,
+        }
+    }
+}
+
+impl Default for Weapon {
+    fn default() -> Self {
+        Weapon::new(WeaponKind::Charged)
+    }
+}
+
 #[derive(Clone)]
 pub struct Player {
     pub player_shot_timeout: f32,
+    pub shot_time: f32,
+    pub weapon: Weapon,
+
+    // Seconds the fire button has been held, up to MAX_CHARGE.
+    pub charge: f32,
+
+    /// Every shot fired this game; a spread counts each of its shots.
+    pub shots_fired: u32,
+
     pub transform: ComponentRef<Transform>,
     pub physics: ComponentRef<Physics>,
 }
@@ -27,69 +163,212 @@ pub struct Player {
 pub const PLAYER_THRUST: f32 = 100.0;
 // Rotation in radians per second.
 pub const PLAYER_TURN_RATE: f32 = 3.0;
-// Seconds between shots
+// Seconds between shots, before difficulty scaling.
 pub const PLAYER_SHOT_TIME: f32 = 0.5;
 
+// Seconds of holding fire for a full charge.
+pub const MAX_CHARGE: f32 = 1.0;
+// Holding fire for less than this is a tap, and fires nothing on release.
+pub const MIN_CHARGE: f32 = 0.25;
+// How much bigger, faster, and stronger a fully charged shot is than
+// a normal one.
+pub const CHARGED_SHOT_SCALE: f32 = 3.0;
+// How much bigger the ship is drawn at full charge.
+pub const CHARGE_SPRITE_GROWTH: f32 = 0.3;
+
 impl Player {
-    pub fn new(transform: ComponentRef<Transform>, physics: ComponentRef<Physics>) -> Self {
+    pub fn new(
+        transform: ComponentRef<Transform>,
+        physics: ComponentRef<Physics>,
+        shot_time: f32,
+    ) -> Self {
         Player {
-            player_shot_timeout: PLAYER_SHOT_TIME,
-            transform: transform.into(),
-            physics: physics.into(),
+            player_shot_timeout: shot_time,
+            shot_time,
+            weapon: Weapon::default(),
+            charge: 0.0,
+            shots_fired: 0,
+            transform,
+            physics,
         }
     }
 
-    pub fn player_handle_input(&mut self, system: &Ecs, input: &InputState, dt: f32) {
+    pub fn player_handle_input(
+        &mut self,
+        system: &Ecs,
+        input: &InputState,
+        config: &GameConfig,
+        dt: f32,
+    ) {
         let mut transform = self.transform.borrow_mut(system).unwrap();
 
-        transform.facing += dt * PLAYER_TURN_RATE * input.xaxis;
+        // The ship turns toward the mouse at the same rate as with the
+        // arrow keys, which win if both are in use.
+        let turn = dt * config.player_turn_rate;
+        if input.xaxis != 0.0 {
+            transform.facing += turn * input.xaxis;
+        } else if let Some(aim) = input.aim {
+            transform.facing = rotate_toward(transform.facing, aim, turn);
+        }
 
         drop(transform);
 
         if input.yaxis > 0.0 {
-            self.player_thrust(system, dt);
+            self.player_thrust(system, config, dt);
         }
     }
 
-    pub fn player_thrust(&mut self, system: &Ecs, dt: f32) {
+    pub fn player_thrust(&mut self, system: &Ecs, config: &GameConfig, dt: f32) {
         let transform = self.transform.borrow(system).unwrap();
         let mut physics = self.physics.borrow_mut(system).unwrap();
-        let direction_vector = vec_from_angle(transform.facing);
-        let thrust_vector = direction_vector * (PLAYER_THRUST);
+        let thrust_vector = transform.forward() * (config.player_thrust);
         physics.velocity += thrust_vector * (dt);
     }
 
+    /// How charged the next shot is, from 0 to 1.
+    pub fn charge_fraction(&self) -> f32 {
+        (self.charge / MAX_CHARGE).min(1.0)
+    }
+
+    /// Pressing fire shoots a normal shot. Rapid fire keeps shooting while
+    /// fire is held. A charged weapon fires nothing until fire is released
+    /// instead: holding it charges up, and releasing shoots a charged shot
+    /// if it was held for at least `MIN_CHARGE` seconds, or a normal one if
+    /// not. Either way it's one shot per press, and nothing fires while the
+    /// weapon is overheated or cooling down between shots.
     pub fn try_fire(
         &mut self,
         system: &Ecs,
-        new_shots_ecs: &mut Ecs,
+        commands: &mut CommandBuffer,
         input: &InputState,
-        assets: &Assets,
+        config: &GameConfig,
+        assets: Option<&Assets>,
         dt: f32,
     ) {
         self.player_shot_timeout -= dt;
-        if input.fire && self.player_shot_timeout < 0.0 {
-            self.fire_player_shot(system, new_shots_ecs, assets);
+        self.weapon.cool(dt);
+        let ready = !self.weapon.overheated && self.player_shot_timeout < 0.0;
+        if self.weapon.kind != WeaponKind::Charged {
+            self.charge = 0.0;
+            let auto_fire = self.weapon.kind == WeaponKind::Rapid && input.fire;
+            if ready && (input.fire_pressed || auto_fire) {
+                self.fire_player_shot(system, commands, config, assets, 0.0);
+            }
+        } else if input.fire_pressed {
+            self.charge = 0.0;
+        } else if input.fire {
+            self.charge = (self.charge + dt).min(MAX_CHARGE);
+        } else if input.fire_released {
+            if ready {
+                let charge = if self.charge >= MIN_CHARGE { self.charge_fraction() } else { 0.0 };
+                self.fire_player_shot(system, commands, config, assets, charge);
+            }
+            self.charge = 0.0;
         }
     }
 
-    pub fn fire_player_shot(&mut self, system: &Ecs, new_shots_ecs: &mut Ecs, assets: &Assets) {
-        self.player_shot_timeout = PLAYER_SHOT_TIME;
-
-        let shot = create_shot(new_shots_ecs);
-        let mut shot_transform = new_shots_ecs.borrow_mut::<Transform>(shot).unwrap();
-        let mut shot_physics = new_shots_ecs.borrow_mut::<Physics>(shot).unwrap();
+    /// Fire whatever the current weapon fires, scaled up by `charge`
+    /// (from 0 to 1). The shots are queued on `commands` to be spawned
+    /// once the world is free.
+    pub fn fire_player_shot(
+        &mut self,
+        system: &Ecs,
+        commands: &mut CommandBuffer,
+        config: &GameConfig,
+        assets: Option<&Assets>,
+        charge: f32,
+    ) {
+        self.player_shot_timeout = self.weapon.cooldown(self.shot_time);
+        self.weapon.add_heat();
+        let scale = 1.0 + (CHARGED_SHOT_SCALE - 1.0) * charge;
+        let damage = scale * self.weapon.damage_factor();
 
         let player_transform = self.transform.borrow(system).unwrap();
-        shot_transform.pos = player_transform.pos;
-        shot_transform.facing = player_transform.facing;
-        let direction = vec_from_angle(shot_transform.facing);
+        let style = ShotStyle {
+            owner: self.transform.entity(system).ok(),
+            scale,
+            damage,
+            // Shots come out of the ship's nose, not its middle.
+            muzzle: PLAYER_BBOX,
+        };
+        let (count, arc) = self.weapon.spread();
+        let speed = config.shot_speed * scale;
+        let (pos, facing) = (player_transform.pos, player_transform.facing);
+        fire_spread(commands, pos, facing, count, arc, speed, style);
+        self.shots_fired += count as u32;
+
+        if let Some(assets) = assets {
+            assets.shot_sound.play().unwrap();
+        }
+    }
+}
 
-        shot_physics.velocity.x = SHOT_SPEED * direction.x;
-        shot_physics.velocity.y = SHOT_SPEED * direction.y;
+/// How the shots from `fire_spread` look and hit.
+#[derive(Debug, Copy, Clone)]
+pub struct ShotStyle {
+    /// Who fired, so the shots can't hit them.
+    pub owner: Option<EntityId>,
+    /// Size, relative to a normal shot.
+    pub scale: f32,
+    /// Damage, relative to a normal shot.
+    pub damage: f32,
+    /// How far from `origin` the shots appear.
+    pub muzzle: f32,
+}
 
-        // TODO: self.shots.push(shot);
-        assets.shot_sound.play().unwrap();
+/// `count` angles spread evenly across `total_arc` radians, centered on
+/// `facing`. A single shot goes straight along `facing`.
+pub fn spread_angles(facing: f32, count: usize, total_arc: f32) -> Vec<f32> {
+    if count <= 1 {
+        return vec![facing; count];
+    }
+    let step = total_arc / (count - 1) as f32;
+    (0..count)
+        .map(|i| facing - total_arc / 2.0 + step * i as f32)
+        .collect()
+}
+
+/// Queue up `count` shots on `commands`, fanned out across `total_arc`
+/// radians around `facing` (see `spread_angles`), each flying out from
+/// `origin` at `speed`.
+pub fn fire_spread(
+    commands: &mut CommandBuffer,
+    origin: Point2,
+    facing: f32,
+    count: usize,
+    total_arc: f32,
+    speed: f32,
+    style: ShotStyle,
+) {
+    for angle in spread_angles(facing, count, total_arc) {
+        let direction = vec_from_angle(angle);
+        let pos = origin + direction * style.muzzle;
+        let velocity = direction * speed;
+
+        commands.push(move |system| {
+            // With no ids left, the shot just doesn't happen.
+            let shot = match create_shot(system) {
+                Ok(shot) => shot,
+                Err(_) => return,
+            };
+            let aimed = (|| -> Result<(), EcsError> {
+                system.borrow_mut::<Projectile>(shot)?.owner = style.owner;
+                let mut shot_transform = system.borrow_mut::<Transform>(shot)?;
+                shot_transform.facing = angle;
+                shot_transform.pos = pos;
+                system.borrow_mut::<Physics>(shot)?.velocity = velocity;
+
+                system.borrow_mut::<Damage>(shot)?.amount *= style.damage;
+                let mut shot_bbox = system.borrow_mut::<BoundingBox>(shot)?;
+                shot_bbox.shape = shot_bbox.shape.scaled(style.scale);
+                system.borrow_mut::<Sprite>(shot)?.scale = style.scale;
+                Ok(())
+            })();
+            // Rather than leave a half-made shot flying around.
+            if aimed.is_err() {
+                let _ = system.remove_entity(shot);
+            }
+        });
     }
 }
 
@@ -104,15 +383,57 @@ impl Tag {
             tag
         }
     }
+
+    /// Every entity tagged as `kind`, in no particular order.
+    pub fn entities_of_type(system: &Ecs, kind: ActorType) -> Vec<EntityId> {
+        system
+            .components_ref::<Tag>()
+            .filter(|(_, tag)| tag.tag == kind)
+            .map(|(id, _)| system.get_parent(id).unwrap())
+            .collect()
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RockSize {
+    Boss,
+    Large,
+    Medium,
+    Small,
+}
+
+impl RockSize {
+    /// The size of the fragments this rock breaks into when destroyed,
+    /// or `None` if it's too small to split.
+    pub fn smaller(self) -> Option<RockSize> {
+        match self {
+            RockSize::Boss => Some(RockSize::Large),
+            RockSize::Large => Some(RockSize::Medium),
+            RockSize::Medium => Some(RockSize::Small),
+            RockSize::Small => None,
+        }
+    }
 }
 
 #[derive(Clone)]
-pub struct Rock;
+pub struct Rock {
+    pub size: RockSize,
+}
+
+impl Rock {
+    pub fn new(size: RockSize) -> Rock {
+        Rock { size }
+    }
+}
 
 #[derive(Clone)]
 pub struct Transform {
     pub pos: Point2,
     pub facing: f32,
+
+    /// How big the entity is relative to its image. Bounding boxes are
+    /// built to match, so what you see is what you hit.
+    pub scale: f32,
 }
 
 impl Default for Transform {
@@ -120,6 +441,25 @@ impl Default for Transform {
         Transform {
             pos: Point2::origin(),
             facing: 0.0,
+            scale: 1.0,
+        }
+    }
+}
+
+impl Transform {
+    /// The unit vector this entity faces along; see `vec_from_angle`.
+    pub fn forward(&self) -> Vector2 {
+        vec_from_angle(self.facing)
+    }
+
+    /// `facing` in degrees clockwise from straight up, from 0 up to 360.
+    pub fn facing_degrees(&self) -> f32 {
+        let degrees = self.facing.to_degrees().rem_euclid(360.0);
+        // A hair under 0 rounds up to 360 in `rem_euclid`; that's 0 too.
+        if degrees >= 360.0 {
+            0.0
+        } else {
+            degrees
         }
     }
 }
@@ -142,67 +482,194 @@ impl Physics {
         }
     }
 
-    pub fn update_actor_position(&mut self, system: &Ecs, dt: f32) {
+    pub fn update_actor_position(&mut self, system: &Ecs, config: &GameConfig, dt: f32) {
         let mut transform = self.transform.borrow_mut(system).unwrap();
 
         // Clamp the velocity to the max efficiently
+        let max_vel = config.max_physics_vel;
         let norm_sq = self.velocity.norm_squared();
-        if norm_sq > MAX_PHYSICS_VEL.powi(2) {
-            self.velocity = self.velocity / norm_sq.sqrt() * MAX_PHYSICS_VEL;
+        if norm_sq > max_vel.powi(2) {
+            self.velocity = self.velocity / norm_sq.sqrt() * max_vel;
         }
         let dv = self.velocity * (dt);
         transform.pos += dv;
         transform.facing += self.ang_vel;
     }
 
-    /// Takes an actor and wraps its position to the bounds of the
-    /// screen, so if it goes off the left side of the screen it
-    /// will re-enter on the right side and so on.
-    pub fn wrap_actor_position(&mut self, system: &Ecs, sx: f32, sy: f32) {
+    /// Shove the actor along `direction` at `speed`, on top of how it's
+    /// already moving, but no faster than `MAX_PHYSICS_VEL` overall. A zero
+    /// `direction` does nothing.
+    pub fn knock_back(&mut self, direction: Vector2, speed: f32) {
+        let norm = direction.norm();
+        if norm == 0.0 {
+            return;
+        }
+        self.velocity += direction / norm * speed;
+        let norm = self.velocity.norm();
+        if norm > MAX_PHYSICS_VEL {
+            self.velocity = self.velocity / norm * MAX_PHYSICS_VEL;
+        }
+    }
+
+    /// Takes an actor and keeps it inside the bounds of the screen. In
+    /// `WrapMode::Torus`, if it goes off the left side of the screen it
+    /// will re-enter on the right side and so on. In `WrapMode::Bounce`
+    /// it bounces off the edge instead.
+    pub fn wrap_actor_position(&mut self, system: &Ecs, sx: f32, sy: f32, mode: WrapMode) {
         let mut transform = self.transform.borrow_mut(system).unwrap();
 
-        // Wrap screen
         let screen_x_bounds = sx / 2.0;
         let screen_y_bounds = sy / 2.0;
-        if transform.pos.x > screen_x_bounds {
-            transform.pos.x -= sx;
-        } else if transform.pos.x < -screen_x_bounds {
-            transform.pos.x += sx;
-        };
-        if transform.pos.y > screen_y_bounds {
-            transform.pos.y -= sy;
-        } else if transform.pos.y < -screen_y_bounds {
-            transform.pos.y += sy;
+        match mode {
+            WrapMode::Torus => {
+                // Wrap screen. Anything still on its way in from outside,
+                // like an edge-spawned rock, is left to arrive.
+                let velocity = self.velocity;
+                if transform.pos.x > screen_x_bounds && velocity.x >= 0.0 {
+                    transform.pos.x -= sx;
+                } else if transform.pos.x < -screen_x_bounds && velocity.x <= 0.0 {
+                    transform.pos.x += sx;
+                };
+                if transform.pos.y > screen_y_bounds && velocity.y >= 0.0 {
+                    transform.pos.y -= sy;
+                } else if transform.pos.y < -screen_y_bounds && velocity.y <= 0.0 {
+                    transform.pos.y += sy;
+                }
+            }
+            WrapMode::Bounce => {
+                // The walls hit, as normals pointing back into the field.
+                let mut walls = Vec::new();
+                if transform.pos.x > screen_x_bounds {
+                    transform.pos.x = screen_x_bounds;
+                    walls.push(Vector2::new(-1.0, 0.0));
+                } else if transform.pos.x < -screen_x_bounds {
+                    transform.pos.x = -screen_x_bounds;
+                    walls.push(Vector2::new(1.0, 0.0));
+                }
+                if transform.pos.y > screen_y_bounds {
+                    transform.pos.y = screen_y_bounds;
+                    walls.push(Vector2::new(0.0, -1.0));
+                } else if transform.pos.y < -screen_y_bounds {
+                    transform.pos.y = -screen_y_bounds;
+                    walls.push(Vector2::new(0.0, 1.0));
+                }
+                for normal in walls {
+                    // Only bounce things still heading into the wall.
+                    if self.velocity.dot(&normal) < 0.0 {
+                        self.velocity = reflect(self.velocity, normal);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// What happens at the edges of the playing field.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WrapMode {
+    /// Leaving one side brings you back on the opposite one.
+    Torus,
+    /// The edges are walls that everything bounces off.
+    Bounce,
+}
+
+impl WrapMode {
+    /// The field size to use for wrapped collision checks. Nothing
+    /// touches across the edges of a bounce field.
+    pub fn collision_field(self, sx: f32, sy: f32) -> (f32, f32) {
+        match self {
+            WrapMode::Torus => (sx, sy),
+            WrapMode::Bounce => (std::f32::INFINITY, std::f32::INFINITY),
+        }
+    }
+}
+
+/// The outline used for collisions, centered on the entity's position.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Shape {
+    /// A circle with the given radius.
+    Circle(f32),
+    /// An axis-aligned box with the given half-width and half-height.
+    /// It doesn't turn with the entity.
+    Aabb(f32, f32),
+}
+
+impl Shape {
+    /// Whether this shape overlaps `other`, when `other` is `delta` away.
+    pub fn overlaps(&self, other: &Shape, delta: Vector2) -> bool {
+        match (*self, *other) {
+            (Shape::Circle(r1), Shape::Circle(r2)) => delta.norm() < r1 + r2,
+            (Shape::Aabb(w1, h1), Shape::Aabb(w2, h2)) => {
+                delta.x.abs() < w1 + w2 && delta.y.abs() < h1 + h2
+            }
+            (Shape::Aabb(w, h), Shape::Circle(r)) => {
+                // The point in the box closest to the circle's center.
+                let closest = Vector2::new(delta.x.max(-w).min(w), delta.y.max(-h).min(h));
+                (delta - closest).norm() < r
+            }
+            (Shape::Circle(_), Shape::Aabb(_, _)) => other.overlaps(self, -delta),
+        }
+    }
+
+    /// The same shape, `factor` times bigger.
+    pub fn scaled(&self, factor: f32) -> Shape {
+        match *self {
+            Shape::Circle(r) => Shape::Circle(r * factor),
+            Shape::Aabb(w, h) => Shape::Aabb(w * factor, h * factor),
         }
     }
 }
 
-// Note: This is actually implemented as a bounding CIRCLE, not a box...
 #[derive(Clone)]
 pub struct BoundingBox {
-    pub bbox_size: f32,
+    pub shape: Shape,
 
     pub transform: ComponentRef<Transform>,
 }
 
 impl BoundingBox {
-    pub fn new(bbox_size: f32, transform: ComponentRef<Transform>) -> Self {
+    pub fn circle(radius: f32, transform: ComponentRef<Transform>) -> Self {
+        BoundingBox {
+            shape: Shape::Circle(radius),
+            transform,
+        }
+    }
+
+    pub fn aabb(half_width: f32, half_height: f32, transform: ComponentRef<Transform>) -> Self {
         BoundingBox {
-            bbox_size,
+            shape: Shape::Aabb(half_width, half_height),
             transform,
         }
     }
 
-    pub fn is_touching(&self, system: &Ecs, other: &BoundingBox) -> bool {
+    /// `sx` and `sy` are the size of the wrapping playing field, so
+    /// things touching across an edge count.
+    pub fn is_touching_wrapped(&self, system: &Ecs, other: &BoundingBox, sx: f32, sy: f32) -> bool {
         let transform = self.transform.borrow(system).unwrap();
         let other_transform = other.transform.borrow(system).unwrap();
 
-        let pdistance = transform.pos - other_transform.pos;
-        
-        pdistance.norm() < (self.bbox_size + other.bbox_size)
+        let delta = wrapped_delta(other_transform.pos, transform.pos, sx, sy);
+
+        self.shape.overlaps(&other.shape, delta)
     }
 }
 
+/// The health a boss gains for each rock it swallows.
+pub const BOSS_ABSORB_HEAL: f32 = 1.0;
+
+/// The health the player loses touching a rock. That's half of
+/// `PLAYER_LIFE`, so the first bump shoves the player away and the second
+/// one is fatal.
+pub const ROCK_CONTACT_DAMAGE: f32 = 0.5;
+
+/// The speed a rock that doesn't kill the player shoves it away with.
+pub const ROCK_KNOCKBACK: f32 = 150.0;
+
+/// How long the player is `Invulnerable` after being shoved, so one bump
+/// only hurts once.
+pub const KNOCKBACK_PROTECTION_TIME: f32 = 0.5;
+
 #[derive(Clone)]
 pub struct Collider {
     pub bounds: ComponentRef<BoundingBox>,
@@ -220,58 +687,396 @@ impl Collider {
         }
     }
 
-    pub fn check_for_collisions(&self, system: &Ecs, assets: &Assets) {
+    /// Touching the player takes `ROCK_CONTACT_DAMAGE` off its health (unless
+    /// it's `Invulnerable`). If it survives, it flashes, is knocked away from
+    /// this collider, and is protected for `KNOCKBACK_PROTECTION_TIME`.
+    ///
+    /// Each shot that hits takes its `Damage` off this collider's health.
+    /// Nothing is hurt by its own shots, and a shot that already hit something
+    /// this step is spent. A hit that leaves some health queues up a
+    /// `DamageFlash` on `commands`. Shots are tried in `ComponentId` order.
+    ///
+    /// Bosses also swallow any ordinary rock they touch, gaining
+    /// `BOSS_ABSORB_HEAL` health for each; see `absorb_rocks`.
+    pub fn check_for_collisions(
+        &self,
+        system: &Ecs,
+        commands: &mut CommandBuffer,
+        field: (f32, f32),
+        assets: Option<&Assets>,
+    ) {
         let rock_bbox = self.bounds.borrow(system).unwrap();
+        let own_entity = self.health.entity(system).unwrap();
+        if system.has_component::<Invulnerable>(own_entity).unwrap().is_some() {
+            return;
+        }
 
-        for player in system.entities_with::<Player>() {
+        let players = system.components_sorted::<Player>().map(|id| system.get_parent(id).unwrap());
+        for player in players {
+            if player == own_entity
+                || system.has_component::<Invulnerable>(player).unwrap().is_some()
+            {
+                continue;
+            }
             let player_bbox = system.get::<BoundingBox>(player).unwrap();
 
-            if rock_bbox.is_touching(system, &player_bbox) {
-                system.borrow_mut::<Health>(player).unwrap().health = 0.0;
+            if rock_bbox.is_touching_wrapped(system, &player_bbox, field.0, field.1) {
+                let mut health = system.borrow_mut::<Health>(player).unwrap();
+                health.health -= ROCK_CONTACT_DAMAGE;
+                if health.health > 0.0 {
+                    let rock_pos = rock_bbox.transform.borrow(system).unwrap().pos;
+                    let player_pos = player_bbox.transform.borrow(system).unwrap().pos;
+                    let away = wrapped_delta(player_pos, rock_pos, field.0, field.1);
+                    system
+                        .borrow_mut::<Physics>(player)
+                        .unwrap()
+                        .knock_back(away, ROCK_KNOCKBACK);
+                    commands.push(move |system| {
+                        let protection = Invulnerable::new(KNOCKBACK_PROTECTION_TIME);
+                        let _ = system.insert(player, protection);
+                        let _ = system.insert(player, DamageFlash::new(DAMAGE_FLASH_TIME));
+                    });
+                }
             }
         }
-        for shot in system.entities_with::<ShotLifetime>() {
+        let shots = system.components_sorted::<Shot>().map(|id| system.get_parent(id).unwrap());
+        for shot in shots {
+            // Once dead, a rock can't be hit again; leave the other
+            // shots for something else.
+            if self.health.borrow(system).unwrap().health <= 0.0 {
+                break;
+            }
+            if system.get::<Projectile>(shot).map_or(false, |p| p.owner == Some(own_entity))
+                || system.borrow::<Lifetime>(shot).map_or(false, |l| l.is_over())
+            {
+                continue;
+            }
             let shot_bbox = system.get::<BoundingBox>(shot).unwrap();
 
-            if rock_bbox.is_touching(system, &shot_bbox) {
-                system.borrow_mut::<ShotLifetime>(shot).unwrap().time = 0.0;
-                self.health.borrow_mut(system).unwrap().health = 0.0;
-                assets.hit_sound.play().unwrap();
+            if rock_bbox.is_touching_wrapped(system, &shot_bbox, field.0, field.1) {
+                system.borrow_mut::<Lifetime>(shot).unwrap().remaining = 0.0;
+                let damage = system.get::<Damage>(shot).unwrap().amount;
+                let mut health = self.health.borrow_mut(system).unwrap();
+                health.health -= damage;
+                if health.health > 0.0 {
+                    commands.push(move |system| {
+                        let _ = system.insert(own_entity, DamageFlash::new(DAMAGE_FLASH_TIME));
+                    });
+                }
+                if let Some(assets) = assets {
+                    assets.play_hit();
+                }
             }
         }
+
+        let is_boss = system.get::<Rock>(own_entity).map_or(false, |r| r.size == RockSize::Boss);
+        if is_boss {
+            self.absorb_rocks(system, commands, field);
+        }
+    }
+
+    /// Swallow the living non-boss rocks touching this collider. Each one
+    /// heals it by `BOSS_ABSORB_HEAL`, and is removed through `commands`
+    /// without scoring or splitting. A swallowed rock's health drops to 0
+    /// straight away, so no other boss can swallow it too.
+    fn absorb_rocks(&self, system: &Ecs, commands: &mut CommandBuffer, field: (f32, f32)) {
+        let own_bbox = self.bounds.borrow(system).unwrap();
+        if self.health.borrow(system).unwrap().health <= 0.0 {
+            return;
+        }
+        let rocks = system.components_sorted::<Rock>().map(|id| system.get_parent(id).unwrap());
+        for rock in rocks {
+            if system.borrow::<Rock>(rock).unwrap().size == RockSize::Boss
+                || system.borrow::<Health>(rock).unwrap().health <= 0.0
+            {
+                continue;
+            }
+            let rock_bbox = system.get::<BoundingBox>(rock).unwrap();
+
+            if own_bbox.is_touching_wrapped(system, &rock_bbox, field.0, field.1) {
+                self.health.borrow_mut(system).unwrap().heal(BOSS_ABSORB_HEAL);
+                system.borrow_mut::<Health>(rock).unwrap().health = 0.0;
+                commands.push(move |system| {
+                    let _ = system.remove_entity(rock);
+                });
+            }
+        }
+    }
+}
+
+/// The points awarded for destroying an entity. Entities without one
+/// are worth nothing.
+#[derive(Clone)]
+pub struct PointValue {
+    pub points: Score,
+}
+
+impl PointValue {
+    pub fn new(points: Score) -> PointValue {
+        PointValue { points }
     }
 }
 
 #[derive(Clone)]
 pub struct Health {
     pub health: f32,
+    /// The most health there can be, which is also the starting health.
+    pub max: f32,
 }
 
 impl Health {
     pub fn new(health: f32) -> Health {
-        Health { health }
+        Health {
+            health,
+            max: health,
+        }
+    }
+
+    /// Add `amount` of health, up to `max`.
+    pub fn heal(&mut self, amount: f32) {
+        self.health = (self.health + amount).min(self.max);
+    }
+}
+
+/// Slowly heals an entity's `Health`, at `rate` per second, up to `max`
+/// (or the health's own `max`, if that's lower). The dead stay dead.
+#[derive(Debug, Clone)]
+pub struct Regen {
+    pub rate: f32,
+    pub max: f32,
+}
+
+impl Regen {
+    pub fn apply(&self, health: &mut Health, dt: f32) {
+        let max = self.max.min(health.max);
+        if health.health > 0.0 && health.health < max {
+            health.health = (health.health + self.rate * dt).min(max);
+        }
     }
 }
 
+/// How much health a shot takes off whatever it hits.
 #[derive(Clone)]
-pub struct ShotLifetime {
+pub struct Damage {
+    pub amount: f32,
+}
+
+impl Damage {
+    pub fn new(amount: f32) -> Damage {
+        Damage { amount }
+    }
+}
+
+/// Marks an entity as a shot, which hurts whatever `Collider` it touches.
+/// How long it lasts is up to its `Lifetime`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Shot;
+
+/// Seconds until an entity is despawned. The simulation counts every
+/// `Lifetime` down and removes the entity once it reaches zero.
+#[derive(Debug, Clone)]
+pub struct Lifetime {
+    pub remaining: f32,
+}
+
+impl Lifetime {
+    pub fn new(remaining: f32) -> Lifetime {
+        Lifetime { remaining }
+    }
+
+    pub fn update(&mut self, dt: f32) {
+        self.remaining -= dt;
+    }
+
+    pub fn is_over(&self) -> bool {
+        self.remaining <= 0.0
+    }
+}
+
+/// Keeps the player from being killed for a while after spawning.
+#[derive(Debug, Clone)]
+pub struct Invulnerable {
     pub time: f32,
 }
 
-impl ShotLifetime {
-    pub fn new(time: f32) -> ShotLifetime {
-        ShotLifetime { time }
+impl Invulnerable {
+    pub fn new(time: f32) -> Invulnerable {
+        Invulnerable { time }
     }
 
-    pub fn handle_shot_timer(&mut self, dt: f32) {
+    pub fn update(&mut self, dt: f32) {
         self.time -= dt;
     }
+
+    pub fn is_over(&self) -> bool {
+        self.time <= 0.0
+    }
+}
+
+/// Whether an entity is drawn. Hides it without removing it; entities
+/// without one are drawn.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Visible(pub bool);
+
+/// Flashes an entity's sprite on and off: it shows for the first half of
+/// each `period` and is hidden for the second. Goes along with
+/// `Invulnerable`, so the player can see they're protected. A `period`
+/// that isn't positive doesn't blink at all.
+#[derive(Debug, Clone)]
+pub struct Blink {
+    pub period: f32,
+    pub elapsed: f32,
+}
+
+impl Blink {
+    pub fn new(period: f32) -> Blink {
+        Blink {
+            period,
+            elapsed: 0.0,
+        }
+    }
+
+    pub fn update(&mut self, dt: f32) {
+        self.elapsed += dt;
+    }
+
+    pub fn is_visible(&self) -> bool {
+        if self.period <= 0.0 {
+            return true;
+        }
+        self.elapsed % self.period < self.period / 2.0
+    }
+}
+
+// Seconds a sprite flashes red after a hit it survives.
+pub const DAMAGE_FLASH_TIME: f32 = 0.15;
+
+/// Tints an entity's sprite red after it takes a hit that doesn't kill
+/// it, fading back to normal as `time` runs out.
+#[derive(Debug, Clone)]
+pub struct DamageFlash {
+    pub time: f32,
+}
+
+impl DamageFlash {
+    pub fn new(time: f32) -> DamageFlash {
+        DamageFlash { time }
+    }
+
+    pub fn update(&mut self, dt: f32) {
+        self.time -= dt;
+    }
+
+    pub fn is_over(&self) -> bool {
+        self.time <= 0.0
+    }
+
+    /// Tint `color` toward red, more so the fresher the hit.
+    pub fn tint(&self, color: graphics::Color) -> graphics::Color {
+        let strength = (self.time / DAMAGE_FLASH_TIME).max(0.0).min(1.0);
+        let fade = 1.0 - strength;
+        graphics::Color::new(color.r, color.g * fade, color.b * fade, color.a)
+    }
+}
+
+/// Marks a shot with whoever fired it, so it can't hit them.
+#[derive(Debug, Clone)]
+pub struct Projectile {
+    pub owner: Option<EntityId>,
+}
+
+impl Projectile {
+    pub fn new(owner: Option<EntityId>) -> Projectile {
+        Projectile { owner }
+    }
+}
+
+// Upward drift of score popups, in pixels per second.
+pub const POPUP_DRIFT_SPEED: f32 = 30.0;
+
+/// Floating text showing the points from a kill. It drifts up and fades
+/// out over `lifetime` seconds, as its entity's `Lifetime` runs down. It
+/// has no physics or bounds, so it never takes part in gameplay.
+#[derive(Clone)]
+pub struct ScorePopup {
+    pub points: Score,
+    pub lifetime: f32,
+
+    // Built lazily, since text can only be made with a `Context`.
+    pub text: Option<graphics::Text>,
+
+    pub transform: ComponentRef<Transform>,
+}
+
+impl ScorePopup {
+    pub fn new(points: Score, lifetime: f32, transform: ComponentRef<Transform>) -> Self {
+        ScorePopup {
+            points,
+            lifetime,
+            text: None,
+            transform,
+        }
+    }
+
+    pub fn update_popup(&mut self, system: &Ecs, dt: f32) {
+        let mut transform = self.transform.borrow_mut(system).unwrap();
+        transform.pos.y += POPUP_DRIFT_SPEED * dt;
+    }
+
+    pub fn build_text(&mut self, ctx: &mut Context, assets: &Assets) -> GameResult<()> {
+        if self.text.is_none() {
+            let text_str = format!("+{}", self.points);
+            self.text = Some(graphics::Text::new(ctx, &text_str, &assets.font)?);
+        }
+        Ok(())
+    }
+
+    pub fn draw_popup(
+        &self,
+        ctx: &mut Context,
+        system: &Ecs,
+        camera: &Camera,
+        world_coords: (u32, u32),
+        field: (f32, f32),
+    ) -> GameResult<()> {
+        let text = match &self.text {
+            Some(text) => text,
+            None => return Ok(()),
+        };
+
+        // Popups are small and short-lived, so the copy nearest the
+        // camera is enough.
+        let transform = self.transform.borrow(system).unwrap();
+        let (screen_w, screen_h) = world_coords;
+        let near = camera.nearest_copy(transform.pos, field);
+        let pos = camera.world_to_screen(screen_w, screen_h, near);
+        let remaining = self
+            .transform
+            .entity(system)
+            .ok()
+            .and_then(|entity| system.try_get::<Lifetime>(entity))
+            .map_or(self.lifetime, |lifetime| lifetime.remaining);
+        let alpha = (remaining / self.lifetime).max(0.0).min(1.0);
+        let drawparams = graphics::DrawParam {
+            dest: pos,
+            offset: graphics::Point2::new(0.5, 0.5),
+            color: Some(graphics::Color::new(1.0, 1.0, 1.0, alpha)),
+            ..Default::default()
+        };
+        graphics::draw_ex(ctx, text, drawparams)
+    }
 }
 
 #[derive(Clone)]
 pub struct Sprite {
     pub tag: ComponentRef<Tag>,
     pub transform: ComponentRef<Transform>,
+
+    // Extra scaling on top of the actor type's usual size.
+    pub scale: f32,
+
+    // Which of the actor type's images to draw, for types with several.
+    pub variant: usize,
 }
 
 impl Sprite {
@@ -279,27 +1084,535 @@ impl Sprite {
         Sprite {
             tag,
             transform,
+            scale: 1.0,
+            variant: 0,
         }
     }
 
-    pub fn draw_actor(
+    /// False if the tag or transform this sprite draws has gone away.
+    pub fn is_valid(&self, system: &Ecs) -> bool {
+        self.tag.is_valid(system) && self.transform.is_valid(system)
+    }
+
+    /// False if the sprite's entity has been hidden with `Visible(false)`.
+    pub fn is_visible(&self, system: &Ecs) -> bool {
+        self.tag
+            .entity(system)
+            .ok()
+            .and_then(|entity| system.try_get::<Visible>(entity))
+            .map_or(true, |visible| visible.0)
+    }
+
+    /// Where and how big to draw this sprite, seen through `camera`. It's
+    /// drawn at `world_pos`, which is where it is unless it's a copy past
+    /// a seam of the field.
+    pub fn draw_param(
         &self,
-        assets: &Assets,
-        ctx: &mut Context,
         system: &Ecs,
+        camera: &Camera,
         world_coords: (u32, u32),
-    ) -> GameResult<()> {
+        world_pos: Point2,
+    ) -> graphics::DrawParam {
         let transform = self.transform.borrow(system).unwrap();
         let (screen_w, screen_h) = world_coords;
-        let pos = world_to_screen_coords(screen_w, screen_h, transform.pos);
-        let drawparams = graphics::DrawParam {
+        let pos = camera.world_to_screen(screen_w, screen_h, world_pos);
+        let tag = &self.tag.borrow(system).unwrap().tag;
+        let scale = transform.scale * self.scale * camera.zoom;
+        let flash = self
+            .tag
+            .entity(system)
+            .ok()
+            .and_then(|entity| system.try_borrow::<DamageFlash>(entity));
+        let color = match flash {
+            Some(flash) => {
+                let base = tag.sprite_color().unwrap_or(graphics::WHITE);
+                Some(flash.tint(base))
+            }
+            None => tag.sprite_color(),
+        };
+        graphics::DrawParam {
             dest: pos,
             rotation: transform.facing as f32,
             offset: graphics::Point2::new(0.5, 0.5),
+            scale: graphics::Point2::new(scale, scale),
+            color,
             ..Default::default()
-        };
+        }
+    }
+
+    /// Draw the sprite everywhere it shows up on a field of size `field`
+    /// that repeats past its seams; see `Camera::wrapped_copies`.
+    pub fn draw_actor(
+        &self,
+        assets: &Assets,
+        ctx: &mut Context,
+        system: &Ecs,
+        camera: &Camera,
+        world_coords: (u32, u32),
+        field: (f32, f32),
+    ) -> GameResult<()> {
         let tag = &self.tag.borrow(system).unwrap().tag;
-        let image = assets.actor_image(tag);
-        graphics::draw_ex(ctx, image, drawparams)
+        let image = assets.actor_image(tag, self.variant);
+        let pos = self.transform.borrow(system).unwrap().pos;
+        for copy in camera.wrapped_copies(pos, field, world_coords) {
+            let drawparams = self.draw_param(system, camera, world_coords, copy);
+            graphics::draw_ex(ctx, image, drawparams)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use super::super::prefabs::{
+        create_player, create_rock, create_shot, rock_scale, PLAYER_LIFE, ROCK_BBOX, ROCK_LIFE,
+    };
+
+    fn thrust_velocity(config: &GameConfig) -> Vector2 {
+        let mut ecs = Ecs::new();
+        let player = create_player(&mut ecs, PLAYER_SHOT_TIME);
+        ecs.borrow_mut::<Player>(player)
+            .unwrap()
+            .player_thrust(&ecs, config, 0.5);
+        let velocity = ecs.borrow::<Physics>(player).unwrap().velocity;
+        velocity
+    }
+
+    #[test]
+    fn test_config_overrides_thrust() {
+        let default_config = GameConfig::default();
+        let strong_config = GameConfig {
+            player_thrust: default_config.player_thrust * 2.0,
+            ..GameConfig::default()
+        };
+
+        let normal = thrust_velocity(&default_config);
+        let strong = thrust_velocity(&strong_config);
+
+        assert_eq!(normal.norm(), PLAYER_THRUST * 0.5);
+        assert_eq!(strong.norm(), normal.norm() * 2.0);
+    }
+
+    // Hold fire for `hold_steps` steps, release it, and return the damage
+    // and speed of every shot fired.
+    fn fire_after_holding(hold_steps: usize) -> Vec<(f32, f32)> {
+        let config = GameConfig::default();
+        let mut ecs = Ecs::new();
+        let mut commands = CommandBuffer::new();
+        let player = create_player(&mut ecs, PLAYER_SHOT_TIME);
+        let mut player_component: Player = ecs.get(player).unwrap();
+        player_component.player_shot_timeout = 0.0;
+
+        let dt = 0.25;
+        let mut input = InputState {
+            fire: true,
+            ..InputState::default()
+        };
+        for _ in 0..hold_steps {
+            input.begin_frame();
+            player_component.try_fire(&ecs, &mut commands, &input, &config, None, dt);
+        }
+        input.fire = false;
+        input.begin_frame();
+        player_component.try_fire(&ecs, &mut commands, &input, &config, None, dt);
+
+        commands.apply(&mut ecs);
+
+        ecs
+            .entities_with::<Shot>()
+            .into_iter()
+            .map(|shot| {
+                let damage = ecs.get::<Damage>(shot).unwrap().amount;
+                let speed = ecs.borrow::<Physics>(shot).unwrap().velocity.norm();
+                (damage, speed)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_rapid_fire_overheats_then_cools() {
+        let config = GameConfig::default();
+        let mut ecs = Ecs::new();
+        let player = create_player(&mut ecs, PLAYER_SHOT_TIME);
+        let mut player_component: Player = ecs.get(player).unwrap();
+        player_component.weapon = Weapon::new(WeaponKind::Rapid);
+        player_component.player_shot_timeout = 0.0;
+
+        let dt = 0.05;
+        let mut input = InputState {
+            fire: true,
+            ..InputState::default()
+        };
+        let mut steps = 0;
+        while !player_component.weapon.overheated {
+            input.begin_frame();
+            let mut commands = CommandBuffer::new();
+            player_component.try_fire(&ecs, &mut commands, &input, &config, None, dt);
+            steps += 1;
+            assert!(steps < 1000, "Rapid fire never overheated.");
+        }
+
+        // Locked out, even with the button held.
+        for _ in 0..10 {
+            input.begin_frame();
+            let mut commands = CommandBuffer::new();
+            player_component.try_fire(&ecs, &mut commands, &input, &config, None, dt);
+            assert!(commands.is_empty(), "Fired while overheated.");
+        }
+
+        input.fire = false;
+        let cool_time = (MAX_HEAT - HEAT_RESUME) / HEAT_COOL_RATE;
+        for _ in 0..(cool_time / dt) as i32 + 1 {
+            input.begin_frame();
+            let mut commands = CommandBuffer::new();
+            player_component.try_fire(&ecs, &mut commands, &input, &config, None, dt);
+        }
+        assert!(!player_component.weapon.overheated);
+
+        input.fire = true;
+        input.begin_frame();
+        let mut commands = CommandBuffer::new();
+        player_component.try_fire(&ecs, &mut commands, &input, &config, None, dt);
+        assert_eq!(commands.len(), 1);
+    }
+
+    #[test]
+    fn test_weapon_shot_counts() {
+        let config = GameConfig::default();
+        let mut ecs = Ecs::new();
+        let player = create_player(&mut ecs, PLAYER_SHOT_TIME);
+        let mut player_component: Player = ecs.get(player).unwrap();
+
+        let expected = [(WeaponKind::Single, 1), (WeaponKind::Spread, SPREAD_SHOT_COUNT)];
+        for &(kind, count) in expected.iter() {
+            let mut commands = CommandBuffer::new();
+            player_component.weapon = Weapon::new(kind);
+            player_component.fire_player_shot(&ecs, &mut commands, &config, None, 0.0);
+
+            assert_eq!(commands.len(), count, "{:?}", kind);
+        }
+    }
+
+    #[test]
+    fn test_shot_spawns_at_the_nose() {
+        let config = GameConfig::default();
+        let mut ecs = Ecs::new();
+        let player = create_player(&mut ecs, PLAYER_SHOT_TIME);
+        let player_pos = Point2::new(10.0, 20.0);
+        {
+            let mut transform = ecs.borrow_mut::<Transform>(player).unwrap();
+            transform.pos = player_pos;
+            // Facing along +x.
+            transform.facing = std::f32::consts::FRAC_PI_2;
+        }
+        let mut player_component: Player = ecs.get(player).unwrap();
+        player_component.weapon = Weapon::new(WeaponKind::Single);
+
+        ecs.scoped(|system, commands| {
+            player_component.fire_player_shot(system, commands, &config, None, 0.0);
+        });
+
+        let shot = ecs.entities_with::<Shot>()[0];
+        let shot_pos = ecs.get::<Transform>(shot).unwrap().pos;
+        let expected = player_pos + Vector2::new(PLAYER_BBOX, 0.0);
+        assert!((shot_pos - expected).norm() < 1e-4);
+    }
+
+    #[test]
+    fn test_charged_shot_beats_tap() {
+        // One shot per press either way, and only once fire is let go.
+        let tap = fire_after_holding(1);
+        assert_eq!(tap.len(), 1);
+        let hold_steps = (MAX_CHARGE / 0.25) as usize + 2;
+        let charged = fire_after_holding(hold_steps);
+        assert_eq!(charged.len(), 1);
+
+        let (tap_damage, tap_speed) = tap[0];
+        let (charged_damage, charged_speed) = charged[0];
+
+        assert!(charged_damage > tap_damage);
+        assert!(charged_speed > tap_speed);
+    }
+
+    #[test]
+    fn test_charged_release_waits_for_cooldown() {
+        let config = GameConfig::default();
+        let mut ecs = Ecs::new();
+        let player = create_player(&mut ecs, PLAYER_SHOT_TIME);
+        let mut player_component: Player = ecs.get(player).unwrap();
+        player_component.player_shot_timeout = 0.0;
+
+        // Two quick taps: the second comes before the cooldown is up.
+        let dt = 0.05;
+        let mut input = InputState::default();
+        let mut shots = Vec::new();
+        for &fire in [true, false, true, false].iter() {
+            input.fire = fire;
+            input.begin_frame();
+            let mut commands = CommandBuffer::new();
+            player_component.try_fire(&ecs, &mut commands, &input, &config, None, dt);
+            shots.push(!commands.is_empty());
+        }
+
+        assert_eq!(shots, vec![false, true, false, false]);
+    }
+
+    #[test]
+    fn test_shot_damage_accumulates() {
+        let mut ecs = Ecs::new();
+        let rock = create_rock(&mut ecs, RockSize::Large).unwrap();
+        let collider: Collider = ecs.get(rock).unwrap();
+        assert_eq!(ROCK_LIFE, 1.0);
+
+        for expected_health in [0.5, 0.0].iter() {
+            let shot = create_shot(&mut ecs).unwrap();
+            ecs.set(shot, Damage::new(0.5)).unwrap();
+            collider.check_for_collisions(&ecs, &mut CommandBuffer::new(), (640.0, 480.0), None);
+            ecs.remove_entity(shot).unwrap();
+
+            let health: Health = ecs.get(rock).unwrap();
+            assert_eq!(health.health, *expected_health);
+        }
+    }
+
+    #[test]
+    fn test_own_shots_dont_hurt() {
+        let mut ecs = Ecs::new();
+        let player = create_player(&mut ecs, PLAYER_SHOT_TIME);
+        ecs.unset::<Invulnerable>(player).unwrap();
+        let collider: Collider = ecs.get(player).unwrap();
+
+        let own_shot = create_shot(&mut ecs).unwrap();
+        ecs.set(own_shot, Projectile::new(Some(player))).unwrap();
+        collider.check_for_collisions(&ecs, &mut CommandBuffer::new(), (640.0, 480.0), None);
+        assert_eq!(ecs.get::<Health>(player).unwrap().health, PLAYER_LIFE);
+        ecs.remove_entity(own_shot).unwrap();
+
+        let enemy = ecs.create_entity();
+        let enemy_shot = create_shot(&mut ecs).unwrap();
+        ecs.set(enemy_shot, Projectile::new(Some(enemy))).unwrap();
+        collider.check_for_collisions(&ecs, &mut CommandBuffer::new(), (640.0, 480.0), None);
+        assert!(ecs.get::<Health>(player).unwrap().health <= 0.0);
+    }
+
+    #[test]
+    fn test_rock_contact_knocks_player_back() {
+        let mut ecs = Ecs::new();
+        let player = create_player(&mut ecs, PLAYER_SHOT_TIME);
+        ecs.unset::<Invulnerable>(player).unwrap();
+        ecs.borrow_mut::<Transform>(player).unwrap().pos = Point2::new(10.0, 0.0);
+        // Already heading away fast, to check the speed cap.
+        ecs.borrow_mut::<Physics>(player).unwrap().velocity = Vector2::new(200.0, 0.0);
+        let rock = create_rock(&mut ecs, RockSize::Large).unwrap();
+        let collider: Collider = ecs.get(rock).unwrap();
+
+        let mut commands = CommandBuffer::new();
+        collider.check_for_collisions(&ecs, &mut commands, (640.0, 480.0), None);
+        commands.apply(&mut ecs);
+
+        let velocity = ecs.get::<Physics>(player).unwrap().velocity;
+        assert!(velocity.x > 200.0, "Not pushed away from the rock: {:?}", velocity);
+        assert!(velocity.norm() <= MAX_PHYSICS_VEL + 1e-3);
+        // A fresh player survives a bump.
+        assert_eq!(ecs.get::<Health>(player).unwrap().health, PLAYER_LIFE - ROCK_CONTACT_DAMAGE);
+        assert!(ecs.has_component::<Invulnerable>(player).unwrap().is_some());
+        assert!(ecs.has_component::<DamageFlash>(player).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_touching_across_the_seam() {
+        let mut ecs = Ecs::new();
+        let left = create_rock(&mut ecs, RockSize::Large).unwrap();
+        let right = create_rock(&mut ecs, RockSize::Large).unwrap();
+        ecs.borrow_mut::<Transform>(left).unwrap().pos = Point2::new(-315.0, 0.0);
+        ecs.borrow_mut::<Transform>(right).unwrap().pos = Point2::new(315.0, 0.0);
+        let left_bbox: BoundingBox = ecs.get(left).unwrap();
+        let right_bbox: BoundingBox = ecs.get(right).unwrap();
+
+        assert!(left_bbox.is_touching_wrapped(&ecs, &right_bbox, 640.0, 480.0));
+        assert!(!left_bbox.is_touching_wrapped(&ecs, &right_bbox, 1000.0, 480.0));
+        // A field that doesn't wrap has no seam to touch across.
+        let no_wrap = (std::f32::INFINITY, std::f32::INFINITY);
+        assert!(!left_bbox.is_touching_wrapped(&ecs, &right_bbox, no_wrap.0, no_wrap.1));
+    }
+
+    #[test]
+    fn test_circle_circle_overlap() {
+        let a = Shape::Circle(10.0);
+        let b = Shape::Circle(5.0);
+
+        assert!(a.overlaps(&b, Vector2::new(14.0, 0.0)));
+        assert!(!a.overlaps(&b, Vector2::new(12.0, 12.0)));
+    }
+
+    #[test]
+    fn test_aabb_aabb_overlap() {
+        let a = Shape::Aabb(10.0, 5.0);
+        let b = Shape::Aabb(2.0, 2.0);
+
+        assert!(a.overlaps(&b, Vector2::new(11.0, 6.0)));
+        assert!(!a.overlaps(&b, Vector2::new(11.0, 8.0)));
+    }
+
+    #[test]
+    fn test_circle_aabb_overlap() {
+        let circle = Shape::Circle(5.0);
+        let aabb = Shape::Aabb(10.0, 10.0);
+
+        // Against a side, then just off a corner, from both sides.
+        assert!(circle.overlaps(&aabb, Vector2::new(14.0, 0.0)));
+        assert!(aabb.overlaps(&circle, Vector2::new(0.0, -14.0)));
+        assert!(!circle.overlaps(&aabb, Vector2::new(14.0, 14.0)));
+        assert!(!aabb.overlaps(&circle, Vector2::new(-14.0, 14.0)));
+    }
+
+    #[test]
+    fn test_rock_scale_matches_bbox_and_sprite() {
+        let mut ecs = Ecs::new();
+        let rock = create_rock(&mut ecs, RockSize::Small).unwrap();
+        let scale = rock_scale(RockSize::Small);
+
+        assert_eq!(ecs.get::<Transform>(rock).unwrap().scale, scale);
+        assert_eq!(ecs.get::<BoundingBox>(rock).unwrap().shape, Shape::Circle(ROCK_BBOX * scale));
+
+        let sprite: Sprite = ecs.get(rock).unwrap();
+        let pos = ecs.get::<Transform>(rock).unwrap().pos;
+        let param = sprite.draw_param(&ecs, &Camera::default(), (640, 480), pos);
+        assert_eq!(param.scale, Point2::new(scale, scale));
+    }
+
+    #[test]
+    fn test_hidden_sprites_arent_drawn() {
+        let mut ecs = Ecs::new();
+        let shown = create_rock(&mut ecs, RockSize::Large).unwrap();
+        let hidden = create_rock(&mut ecs, RockSize::Large).unwrap();
+        ecs.set(hidden, Visible(false)).unwrap();
+
+        let drawn: Vec<_> = ecs
+            .components_ref::<Sprite>()
+            .filter(|(_, sprite)| sprite.is_visible(&ecs))
+            .map(|(id, _)| ecs.get_parent(id).unwrap())
+            .collect();
+
+        assert_eq!(drawn, vec![shown]);
+    }
+
+    #[test]
+    fn test_bounce_off_the_right_wall() {
+        let run = |mode| {
+            let mut ecs = Ecs::new();
+            let rock = create_rock(&mut ecs, RockSize::Large).unwrap();
+            ecs.borrow_mut::<Transform>(rock).unwrap().pos = Point2::new(325.0, 0.0);
+            let mut physics: Physics = ecs.get(rock).unwrap();
+            physics.velocity = Vector2::new(50.0, 10.0);
+            physics.wrap_actor_position(&ecs, 640.0, 480.0, mode);
+            let pos = ecs.get::<Transform>(rock).unwrap().pos;
+            (pos, physics.velocity)
+        };
+
+        let (pos, velocity) = run(WrapMode::Bounce);
+        assert_eq!(pos, Point2::new(320.0, 0.0));
+        assert_eq!(velocity, Vector2::new(-50.0, 10.0));
+
+        let (pos, velocity) = run(WrapMode::Torus);
+        assert_eq!(pos, Point2::new(-315.0, 0.0));
+        assert_eq!(velocity, Vector2::new(50.0, 10.0));
+    }
+
+    #[test]
+    fn test_blink_phase() {
+        let mut blink = Blink::new(0.2);
+        assert!(blink.is_visible());
+
+        blink.update(0.05);
+        assert!(blink.is_visible());
+        blink.update(0.1);
+        assert!(!blink.is_visible());
+
+        // And again on the next period.
+        blink.update(0.1);
+        assert!(blink.is_visible());
+    }
+
+    #[test]
+    fn test_blink_without_a_period_stays_visible() {
+        for &period in &[0.0, -0.2] {
+            let mut blink = Blink::new(period);
+            assert!(blink.is_visible());
+            blink.update(0.05);
+            assert!(blink.is_visible());
+        }
+    }
+
+    #[test]
+    fn test_entities_of_type_matches_tag() {
+        let mut ecs = Ecs::new();
+        let player = create_player(&mut ecs, PLAYER_SHOT_TIME);
+        let rock = create_rock(&mut ecs, RockSize::Large).unwrap();
+        let small_rock = create_rock(&mut ecs, RockSize::Small).unwrap();
+        let shot = create_shot(&mut ecs).unwrap();
+
+        let mut rocks = Tag::entities_of_type(&ecs, ActorType::Rock);
+        rocks.sort();
+        let mut expected = vec![rock, small_rock];
+        expected.sort();
+        assert_eq!(rocks, expected);
+        assert_eq!(Tag::entities_of_type(&ecs, ActorType::Player), vec![player]);
+        assert_eq!(Tag::entities_of_type(&ecs, ActorType::Shot), vec![shot]);
+        assert!(Tag::entities_of_type(&ecs, ActorType::Boss).is_empty());
+    }
+
+    #[test]
+    fn test_forward() {
+        let transform = Transform::default();
+        assert_eq!(transform.forward(), vec_from_angle(0.0));
+
+        let turned = Transform {
+            facing: 1.0,
+            ..Transform::default()
+        };
+        assert_eq!(turned.forward(), vec_from_angle(1.0));
+    }
+
+    #[test]
+    fn test_fire_spread_fans_evenly() {
+        let mut ecs = Ecs::new();
+        let mut commands = CommandBuffer::new();
+        let style = ShotStyle {
+            owner: None,
+            scale: 1.0,
+            damage: 1.0,
+            muzzle: 0.0,
+        };
+        let facing = 1.0;
+        fire_spread(&mut commands, Point2::origin(), facing, 3, 0.4, 100.0, style);
+        commands.apply(&mut ecs);
+
+        let mut angles: Vec<f32> = ecs
+            .entities_with::<Shot>()
+            .into_iter()
+            .map(|shot| ecs.get::<Transform>(shot).unwrap().facing)
+            .collect();
+        angles.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let expected = [facing - 0.2, facing, facing + 0.2];
+        assert_eq!(angles.len(), expected.len());
+        for (angle, expected) in angles.iter().zip(expected.iter()) {
+            assert!((angle - expected).abs() < 1e-6, "{} != {}", angle, expected);
+        }
+        assert_eq!(spread_angles(facing, 1, 0.4), vec![facing]);
+    }
+
+    #[test]
+    fn test_facing_degrees() {
+        use std::f32::consts::PI;
+        let degrees = |facing| Transform { facing, ..Transform::default() }.facing_degrees();
+
+        assert!((degrees(-PI / 2.0) - 270.0).abs() < 1e-3);
+        assert!((degrees(PI / 2.0) - 90.0).abs() < 1e-3);
+        assert!((degrees(5.0 * PI) - 180.0).abs() < 1e-3);
+        assert_eq!(degrees(0.0), 0.0);
+        assert_eq!(degrees(-1e-7), 0.0);
     }
 }
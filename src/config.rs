@@ -0,0 +1,204 @@
+use ggez::Context;
+use std::io::Read;
+
+use super::components::{WeaponKind, WrapMode, PLAYER_SHOT_TIME, PLAYER_THRUST, PLAYER_TURN_RATE};
+use super::event_loop::DESIRED_FPS;
+use super::prefabs::{
+    SpawnPattern, ESCAPE_CORRIDOR_ANGLE, ROCK_SPAWN_MAX_RADIUS, ROCK_SPAWN_MIN_RADIUS,
+    START_ROCKS,
+};
+use super::simulation::{PRESSURE_INTERVAL, PRESSURE_TIME};
+use super::{MAX_PHYSICS_VEL, MAX_ROCK_VEL, SHOT_SPEED};
+
+pub const CONFIG_PATH: &str = "/config.toml";
+
+/// The least `rock_spawn_max_radius` can be past `rock_spawn_min_radius`,
+/// so a wave's ring of rocks has some room to spread out in.
+pub const MIN_SPAWN_RING_WIDTH: f32 = 10.0;
+
+/// *********************************************************************
+/// The gameplay tunables, loaded from `/config.toml` so the feel of the
+/// game can be tweaked without recompiling. Anything missing from the
+/// file keeps its default, which is the matching constant.
+/// **********************************************************************
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GameConfig {
+    /// Acceleration in pixels per second.
+    pub player_thrust: f32,
+
+    /// Rotation in radians per second.
+    pub player_turn_rate: f32,
+
+    /// Seconds between shots, before difficulty scaling.
+    pub player_shot_time: f32,
+
+    pub shot_speed: f32,
+    pub max_physics_vel: f32,
+
+    /// The maximum rock speed, before difficulty scaling.
+    pub max_rock_vel: f32,
+
+    /// Rocks in the first wave, before difficulty scaling.
+    pub start_rocks: i32,
+
+    /// Where each new wave comes from, e.g. `spawn_pattern = "edges"`.
+    pub spawn_pattern: SpawnPattern,
+
+    /// How far from the player a new wave's rocks spawn, when they
+    /// spawn in a ring.
+    pub rock_spawn_min_radius: f32,
+    pub rock_spawn_max_radius: f32,
+
+    /// How wide a cone in front of the player each wave leaves clear, in
+    /// radians. Zero lets rocks spawn anywhere.
+    pub escape_angle: f32,
+
+    /// Seconds on a level before extra rocks start arriving, and the
+    /// seconds between them after that.
+    pub pressure_time: f32,
+    pub pressure_interval: f32,
+
+    /// The size of the playing field, e.g. `playfield = [1600.0, 1200.0]`.
+    /// Without one, the field is the size the window opens at.
+    pub playfield: Option<(f32, f32)>,
+
+    /// What happens at the edges of the field, e.g. `wrap_mode = "bounce"`.
+    pub wrap_mode: WrapMode,
+
+    /// The volume of every sound, from 0 to 1.
+    pub master_volume: f32,
+
+    /// The weapon the player starts with, e.g. `weapon = "spread"`.
+    pub weapon: WeaponKind,
+
+    /// How many times a second the game updates. Each update moves the
+    /// game on by `1 / target_fps` seconds.
+    pub target_fps: u32,
+}
+
+impl Default for GameConfig {
+    fn default() -> Self {
+        GameConfig {
+            player_thrust: PLAYER_THRUST,
+            player_turn_rate: PLAYER_TURN_RATE,
+            player_shot_time: PLAYER_SHOT_TIME,
+            shot_speed: SHOT_SPEED,
+            max_physics_vel: MAX_PHYSICS_VEL,
+            max_rock_vel: MAX_ROCK_VEL,
+            start_rocks: START_ROCKS,
+            spawn_pattern: SpawnPattern::Ring,
+            rock_spawn_min_radius: ROCK_SPAWN_MIN_RADIUS,
+            rock_spawn_max_radius: ROCK_SPAWN_MAX_RADIUS,
+            escape_angle: ESCAPE_CORRIDOR_ANGLE,
+            pressure_time: PRESSURE_TIME,
+            pressure_interval: PRESSURE_INTERVAL,
+            playfield: None,
+            wrap_mode: WrapMode::Torus,
+            master_volume: 1.0,
+            weapon: WeaponKind::Charged,
+            target_fps: DESIRED_FPS,
+        }
+    }
+}
+
+impl GameConfig {
+    /// Parse a config, then fix anything in it the game can't run with;
+    /// see `normalized`.
+    pub fn from_toml(text: &str) -> Result<GameConfig, toml::de::Error> {
+        toml::from_str(text).map(GameConfig::normalized)
+    }
+
+    /// This config with values the game can't run with fixed up, saying
+    /// what was changed: spawn radii the wrong way around are swapped,
+    /// and the ring they make is widened to `MIN_SPAWN_RING_WIDTH`.
+    pub fn normalized(mut self) -> GameConfig {
+        if self.rock_spawn_max_radius < self.rock_spawn_min_radius {
+            println!(
+                "rock_spawn_max_radius ({}) is less than rock_spawn_min_radius ({}); swapping them",
+                self.rock_spawn_max_radius, self.rock_spawn_min_radius
+            );
+            std::mem::swap(&mut self.rock_spawn_min_radius, &mut self.rock_spawn_max_radius);
+        }
+        let narrowest = self.rock_spawn_min_radius + MIN_SPAWN_RING_WIDTH;
+        if self.rock_spawn_max_radius < narrowest || self.rock_spawn_max_radius.is_nan() {
+            println!(
+                "rock_spawn_max_radius ({}) is too close to rock_spawn_min_radius; using {}",
+                self.rock_spawn_max_radius, narrowest
+            );
+            self.rock_spawn_max_radius = narrowest;
+        }
+        self
+    }
+
+    /// `target_fps`, but never zero.
+    pub fn target_fps(&self) -> u32 {
+        self.target_fps.max(1)
+    }
+
+    /// The seconds each fixed update covers.
+    pub fn timestep(&self) -> f32 {
+        1.0 / self.target_fps() as f32
+    }
+
+    /// Load the config file. If it's missing or can't be read, this
+    /// reports why and falls back to the defaults.
+    pub fn load(ctx: &mut Context) -> GameConfig {
+        if !ctx.filesystem.exists(CONFIG_PATH) {
+            return GameConfig::default();
+        }
+
+        let mut text = String::new();
+        let read_result = ctx
+            .filesystem
+            .open(CONFIG_PATH)
+            .map_err(|e| e.to_string())
+            .and_then(|mut file| file.read_to_string(&mut text).map_err(|e| e.to_string()));
+        if let Err(e) = read_result {
+            println!("Could not read {}: {}", CONFIG_PATH, e);
+            return GameConfig::default();
+        }
+
+        GameConfig::from_toml(&text).unwrap_or_else(|e| {
+            println!("Could not parse {}: {}", CONFIG_PATH, e);
+            GameConfig::default()
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_partial_config_keeps_defaults() {
+        let config = GameConfig::from_toml("player_thrust = 150.0").unwrap();
+
+        assert_eq!(config.player_thrust, 150.0);
+        assert_eq!(config.shot_speed, SHOT_SPEED);
+        assert_eq!(GameConfig::from_toml("").unwrap(), GameConfig::default());
+    }
+
+    #[test]
+    fn test_spawn_radii_are_normalized() {
+        let swapped =
+            GameConfig::from_toml("rock_spawn_min_radius = 300.0\nrock_spawn_max_radius = 100.0");
+        let swapped = swapped.unwrap();
+        assert_eq!(swapped.rock_spawn_min_radius, 100.0);
+        assert_eq!(swapped.rock_spawn_max_radius, 300.0);
+
+        let equal =
+            GameConfig::from_toml("rock_spawn_min_radius = 150.0\nrock_spawn_max_radius = 150.0");
+        let equal = equal.unwrap();
+        assert_eq!(equal.rock_spawn_min_radius, 150.0);
+        assert_eq!(equal.rock_spawn_max_radius, 150.0 + MIN_SPAWN_RING_WIDTH);
+    }
+
+    #[test]
+    fn test_playfield_from_toml() {
+        let config = GameConfig::from_toml("playfield = [1600.0, 1200.0]").unwrap();
+
+        assert_eq!(config.playfield, Some((1600.0, 1200.0)));
+        assert_eq!(GameConfig::default().playfield, None);
+    }
+}
@@ -0,0 +1,66 @@
+use std::str::FromStr;
+
+use super::config::GameConfig;
+
+/// *********************************************************************
+/// The difficulty is picked once at startup and scales the handful of
+/// tunables that make the game harder: how fast rocks move, how many
+/// rocks each level spawns, and how long the player waits between shots.
+/// **********************************************************************
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Difficulty {
+    Easy,
+    Normal,
+    Hard,
+}
+
+impl Default for Difficulty {
+    fn default() -> Self {
+        Difficulty::Normal
+    }
+}
+
+impl Difficulty {
+    /// The maximum speed of a newly spawned rock.
+    pub fn max_rock_vel(self, config: &GameConfig) -> f32 {
+        let scale = match self {
+            Difficulty::Easy => 0.75,
+            Difficulty::Normal => 1.0,
+            Difficulty::Hard => 1.5,
+        };
+        config.max_rock_vel * scale
+    }
+
+    /// The number of rocks spawned at the start of `level`.
+    pub fn rocks_for_level(self, config: &GameConfig, level: i32) -> i32 {
+        let extra = match self {
+            Difficulty::Easy => -2,
+            Difficulty::Normal => 0,
+            Difficulty::Hard => 3,
+        };
+        (config.start_rocks + extra).max(0) + level
+    }
+
+    /// Seconds between player shots.
+    pub fn player_shot_time(self, config: &GameConfig) -> f32 {
+        let scale = match self {
+            Difficulty::Easy => 0.8,
+            Difficulty::Normal => 1.0,
+            Difficulty::Hard => 1.5,
+        };
+        config.player_shot_time * scale
+    }
+}
+
+impl FromStr for Difficulty {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "easy" => Ok(Difficulty::Easy),
+            "normal" => Ok(Difficulty::Normal),
+            "hard" => Ok(Difficulty::Hard),
+            _ => Err(format!("Unknown difficulty: {}", s)),
+        }
+    }
+}
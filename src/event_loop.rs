@@ -1,14 +1,22 @@
-use ggez::event::{EventHandler, Keycode, Mod};
+use ggez::event::{EventHandler, Keycode, Mod, MouseState};
 use ggez::graphics;
 use ggez::timer;
 use ggez::{Context, GameResult};
 
-use super::better_ecs::{Ecs, EntityId};
-use super::components::{
-    Health, Physics, Player, Rock, ShotLifetime, Sprite, Transform, Collider
-};
-
-use super::prefabs::{create_player, create_rocks};
+use super::better_ecs::EcsStats;
+use super::camera::{Camera, ZOOM_STEP};
+use super::components::{Player, ScorePopup, Sprite, Transform, WrapMode};
+use super::config::GameConfig;
+use super::difficulty::Difficulty;
+use super::hud::{HudLayout, WindowState, HUD_LINE_HEIGHT};
+use super::indicators::{draw_offscreen_indicators, draw_wrap_warnings, wrap_warnings};
+use super::minimap::draw_minimap;
+use super::profiler::{Profiler, PROFILE_FRAMES};
+use super::records::{format_time, Records};
+use super::replay::{Playback, Replay, ReplayMode, REPLAY_PATH};
+use super::savegame::{SavedGame, SAVEGAME_PATH};
+use super::simulation::{GameState, Simulation};
+use super::util::seeded_rng;
 
 use super::{print_instructions, Assets, InputState};
 
@@ -17,121 +25,411 @@ use super::{print_instructions, Assets, InputState};
 /// game's "global" state, it keeps track of everything we need for
 /// actually running the game.
 ///
-/// Our game objects are simply a vector for each actor type, and we
-/// probably mingle gameplay-state (like score) and hardware-state
-/// (like `gui_dirty`) a little more than we should, but for something
-/// this small it hardly matters.
+/// The gameplay itself lives in the `Simulation`; `MainState` wraps it
+/// with the things that need a window: assets, input, and the HUD.
 /// **********************************************************************
 
-type Score = u32;
-
 pub struct MainState {
-    player: EntityId,
-    level: i32,
-    score: Score,
     assets: Assets,
-    screen_width: u32,
-    screen_height: u32,
+    window: WindowState,
+    /// The size of the world, which needn't match the window; the camera
+    /// follows the player around it.
+    playfield: (f32, f32),
     input: InputState,
-    gui_dirty: bool,
     score_display: graphics::Text,
     level_display: graphics::Text,
-
-    system: Ecs,
+    time_display: graphics::Text,
+    pause_display: graphics::Text,
+    quit_display: graphics::Text,
+    // Built when the game ends, since it shows the final stats.
+    game_over_display: Vec<graphics::Text>,
+    // Rebuilt every `PROFILE_FRAMES` while the profiler is on.
+    profile_display: Vec<graphics::Text>,
+    // Live entity and component counts, and the ship's heading in whole
+    // degrees, shown with F4. The text is only rebuilt when they change.
+    show_ecs_stats: bool,
+    ecs_stats: Option<(EcsStats, Option<i32>)>,
+    ecs_stats_display: Vec<graphics::Text>,
+
+    camera: Camera,
+    /// The background color in use, which changes with the level.
+    background: graphics::Color,
+
+    /// How fast game time runs compared to real time, for slow motion.
+    /// 1.0 is normal speed.
+    time_scale: f32,
+
+    /// Real seconds spent playing, not counting pauses.
+    elapsed: f32,
+    records: Records,
+
+    // Set when recording a replay or playing one back.
+    recording: Option<Replay>,
+    playback: Option<Playback>,
+
+    sim: Simulation,
 }
 
 impl MainState {
-    pub fn new(ctx: &mut Context) -> GameResult<MainState> {
+    pub fn new(
+        ctx: &mut Context,
+        difficulty: Difficulty,
+        replay_mode: ReplayMode,
+    ) -> GameResult<MainState> {
         ctx.print_resource_stats();
-        graphics::set_background_color(ctx, (0, 0, 0, 255).into());
+        graphics::set_background_color(ctx, level_background(0));
 
         println!("Game resource path: {:?}", ctx.filesystem);
 
-        print_instructions();
+        // A replay brings its own seed, difficulty, and tunables.
+        let (seed, difficulty, config, playback) = match replay_mode {
+            ReplayMode::Playback => {
+                let replay = Replay::load(ctx)?;
+                println!("Playing back {}", REPLAY_PATH);
+                let config = replay.config.clone();
+                (replay.seed, replay.difficulty, config, Some(Playback::new(replay)))
+            }
+            _ => (rand::random(), difficulty, GameConfig::load(ctx), None),
+        };
+        let recording = match replay_mode {
+            ReplayMode::Record => Some(Replay::new(seed, difficulty, config.clone())),
+            _ => None,
+        };
 
-        let mut entity_system = Ecs::new();
+        print_instructions(difficulty);
 
-        let assets = Assets::new(ctx)?;
+        let mut assets = Assets::new(ctx)?;
+        assets.set_volume(config.master_volume);
         let score_disp = graphics::Text::new(ctx, "score", &assets.font)?;
         let level_disp = graphics::Text::new(ctx, "level", &assets.font)?;
-
-        let player = create_player(&mut entity_system);
-        let player_transform: Transform = entity_system.get(player).unwrap();
-        create_rocks(&mut entity_system, 5, player_transform.pos, 100.0, 250.0);
+        let pause_disp = graphics::Text::new(ctx, "Paused", &assets.font)?;
+        let quit_disp = graphics::Text::new(ctx, "Quit? Y/N", &assets.font)?;
+
+        let screen_width = ctx.conf.window_mode.width;
+        let screen_height = ctx.conf.window_mode.height;
+        let playfield = config
+            .playfield
+            .unwrap_or((screen_width as f32, screen_height as f32));
+        let mut sim =
+            Simulation::new(difficulty, config, seeded_rng(seed), playfield.0, playfield.1);
+
+        // Carry on with the last game, if it was quit partway through.
+        let mut elapsed = 0.0;
+        if replay_mode == ReplayMode::Off {
+            match SavedGame::load(ctx) {
+                Ok(Some(saved)) => {
+                    println!("Continuing the game saved in {}", SAVEGAME_PATH);
+                    sim = saved.restore();
+                    elapsed = saved.elapsed;
+                }
+                Ok(None) => (),
+                Err(e) => println!("{}; starting a new game", e),
+            }
+        }
+        let time_disp = graphics::Text::new(ctx, &format_time(elapsed), &assets.font)?;
 
         let s = MainState {
-            player,
-            level: 0,
-            score: 0,
             assets,
-            screen_width: ctx.conf.window_mode.width,
-            screen_height: ctx.conf.window_mode.height,
+            window: WindowState::new(screen_width, screen_height),
+            playfield,
             input: InputState::default(),
-            gui_dirty: true,
             score_display: score_disp,
             level_display: level_disp,
-
-            system: entity_system,
+            time_display: time_disp,
+            pause_display: pause_disp,
+            quit_display: quit_disp,
+            game_over_display: Vec::new(),
+            profile_display: Vec::new(),
+            show_ecs_stats: false,
+            ecs_stats: None,
+            ecs_stats_display: Vec::new(),
+
+            camera: Camera::default(),
+            background: level_background(0),
+            time_scale: 1.0,
+            elapsed,
+            records: Records::load(ctx).unwrap_or_else(|e| {
+                println!("{}", e);
+                Records::default()
+            }),
+
+            recording,
+            playback,
+
+            sim,
         };
 
         Ok(s)
     }
 
-    pub fn clear_dead_stuff(&mut self) {
-        let mut removals =
-            self.system
-                .components_ref::<Health>()
-                .filter(|(id, actor)| {
-                    self.system.get_parent(*id).unwrap() != self.player && actor.health <= 0.0
-                }).map(|(id, _)| self.system.get_parent(id).unwrap())
-                .collect::<Vec<_>>();
-
-        let score_increase = removals.len() as Score;
-        if score_increase != 0 {
-            self.score += score_increase;
-            self.gui_dirty = true;
+    pub fn update_ui(&mut self, ctx: &mut Context) {
+        let score_str = format!("Score: {}  x{}", self.sim.score, self.sim.combo.multiplier());
+        let level_str = format!("Level: {}", self.sim.level);
+        let score_text = graphics::Text::new(ctx, &score_str, &self.assets.font).unwrap();
+        let level_text = graphics::Text::new(ctx, &level_str, &self.assets.font).unwrap();
+
+        self.score_display = score_text;
+        self.level_display = level_text;
+    }
+
+    /// Add real time spent playing to the session timer.
+    pub fn update_timer(&mut self, ctx: &mut Context, real_seconds: f32) {
+        let shown = self.elapsed as u32;
+        self.elapsed += real_seconds;
+        if self.elapsed as u32 != shown {
+            let time_text = graphics::Text::new(ctx, &format_time(self.elapsed), &self.assets.font);
+            self.time_display = time_text.unwrap();
+        }
+    }
+
+    /// Show the final stats, and keep the survival time if it's a record.
+    pub fn end_game(&mut self, ctx: &mut Context) -> GameResult<()> {
+        self.save_recording(ctx);
+        if let Err(e) = SavedGame::delete(ctx) {
+            println!("Could not delete saved game: {}", e);
+        }
+        self.assets.thrust_sound.stop();
+        if self.records.add_time(self.elapsed) {
+            if let Err(e) = self.records.save(ctx) {
+                println!("Could not save records: {}", e);
+            }
         }
 
-        removals.extend(
-            self.system
-            .components_ref::<ShotLifetime>()
-            .filter(|(_, shot)| shot.time <= 0.0)
-            .map(|(id, _)| self.system.get_parent(id).unwrap())
-            .collect::<Vec<_>>()
+        let stats = format!(
+            "Score: {}  Level: {}  Time: {}",
+            self.sim.score,
+            self.sim.level,
+            format_time(self.elapsed)
+        );
+        let shot_stats = self.sim.shot_stats();
+        let accuracy = shot_stats
+            .accuracy()
+            .map_or("-".to_owned(), |accuracy| format!("{:.0}%", accuracy));
+        let shooting = format!(
+            "Shots: {}  Rocks: {}  Accuracy: {}",
+            shot_stats.shots_fired, shot_stats.rocks_destroyed, accuracy
         );
+        let best = format!("Best time: {}", format_time(self.records.best_time));
+        let lines = [
+            "GAME OVER",
+            &stats,
+            &shooting,
+            &best,
+            "Press R to restart / Esc to quit",
+        ];
+        self.game_over_display = lines
+            .iter()
+            .map(|line| graphics::Text::new(ctx, line, &self.assets.font))
+            .collect::<GameResult<_>>()?;
+        Ok(())
+    }
 
-        for id in removals {
-            self.system.remove_entity(id).unwrap();
+    /// Start a fresh game with the same difficulty and tunables.
+    pub fn restart(&mut self, ctx: &mut Context) -> GameResult<()> {
+        let profiler = std::mem::replace(&mut self.sim.profiler, Profiler::default());
+        self.sim = Simulation::new(
+            self.sim.difficulty,
+            self.sim.config.clone(),
+            seeded_rng(rand::random()),
+            self.playfield.0,
+            self.playfield.1,
+        );
+        self.sim.profiler = profiler;
+        self.playback = None;
+        self.assets.thrust_sound.stop();
+        self.camera = Camera::default();
+        self.input = InputState::default();
+        self.elapsed = 0.0;
+        self.time_display = graphics::Text::new(ctx, &format_time(0.0), &self.assets.font)?;
+        self.game_over_display.clear();
+        Ok(())
+    }
+
+    /// Turn the profiler's latest averages into text, once it has seen
+    /// enough frames, and start a fresh batch.
+    fn update_profile_display(&mut self, ctx: &mut Context) -> GameResult<()> {
+        if self.sim.profiler.frames() < PROFILE_FRAMES {
+            return Ok(());
         }
+        let font = &self.assets.font;
+        self.profile_display = self
+            .sim
+            .profiler
+            .report()
+            .iter()
+            .map(|line| graphics::Text::new(ctx, line, font))
+            .collect::<GameResult<_>>()?;
+        self.sim.profiler.reset();
+        Ok(())
     }
 
-    pub fn check_for_level_respawn(&mut self) {
-        if self.system.entities_with::<Rock>().is_empty() {
-            let transform: Transform = self.system.get(self.player).unwrap();
-
-            self.level += 1;
-            self.gui_dirty = true;
-            create_rocks(
-                &mut self.system,
-                self.level + 5,
-                transform.pos,
-                100.0,
-                250.0,
+    /// Rebuild the F4 overlay's text, if anything on it has changed.
+    fn update_ecs_stats_display(&mut self, ctx: &mut Context) -> GameResult<()> {
+        let heading = self
+            .sim
+            .system
+            .try_borrow::<Transform>(self.sim.player)
+            .map(|transform| transform.facing_degrees() as i32);
+        let shown = (self.sim.system.stats(), heading);
+        if self.ecs_stats.as_ref() == Some(&shown) {
+            return Ok(());
+        }
+        let mut lines = shown.0.report();
+        if let Some(heading) = heading {
+            lines.insert(0, format!("Heading: {} deg", heading));
+        }
+        let font = &self.assets.font;
+        self.ecs_stats_display = lines
+            .iter()
+            .map(|line| graphics::Text::new(ctx, line, font))
+            .collect::<GameResult<_>>()?;
+        self.ecs_stats = Some(shown);
+        Ok(())
+    }
+
+    fn hud_layout(&self) -> HudLayout {
+        self.window.layout()
+    }
+
+    /// Switch between windowed and fullscreen, then pick up the window's
+    /// new size.
+    fn toggle_fullscreen(&mut self, ctx: &mut Context) -> GameResult<()> {
+        let fullscreen = self.window.toggle_fullscreen();
+        if let Err(e) = graphics::set_fullscreen(ctx, fullscreen) {
+            // Still in the old mode.
+            self.window.toggle_fullscreen();
+            return Err(e);
+        }
+        let (width, height) = graphics::get_size(ctx);
+        self.resize(ctx, width, height)
+    }
+
+    /// Keep drawing at one pixel per unit, with the HUD anchored to the
+    /// new edges.
+    fn resize(&mut self, ctx: &mut Context, width: u32, height: u32) -> GameResult<()> {
+        self.window.resize(width, height);
+        let screen = graphics::Rect::new(0.0, 0.0, width as f32, height as f32);
+        graphics::set_screen_coordinates(ctx, screen)
+    }
+
+    /// A bar under the level showing how hot the weapon is. It turns red
+    /// while the weapon is locked out.
+    fn draw_heat_bar(&self, ctx: &mut Context) -> GameResult<()> {
+        let weapon = match self.sim.system.try_borrow::<Player>(self.sim.player) {
+            Some(player) => player.weapon.clone(),
+            None => return Ok(()),
+        };
+        let layout = self.hud_layout();
+        let pos = HudLayout::below(layout.top_left(), 1.0, HUD_LINE_HEIGHT);
+        let outline = graphics::Rect::new(pos.x, pos.y, 100.0, 6.0);
+        let fill = graphics::Rect::new(pos.x, pos.y, 100.0 * weapon.heat_fraction(), 6.0);
+
+        let old_color = graphics::get_color(ctx);
+        graphics::set_color(ctx, graphics::Color::new(1.0, 1.0, 1.0, 0.5))?;
+        graphics::rectangle(ctx, graphics::DrawMode::Line(1.0), outline)?;
+        let fill_color = if weapon.overheated {
+            graphics::Color::new(1.0, 0.2, 0.2, 1.0)
+        } else {
+            graphics::Color::new(1.0, 0.6, 0.2, 1.0)
+        };
+        graphics::set_color(ctx, fill_color)?;
+        graphics::rectangle(ctx, graphics::DrawMode::Fill, fill)?;
+        graphics::set_color(ctx, old_color)
+    }
+
+    fn draw_profile(&self, ctx: &mut Context) -> GameResult<()> {
+        // Below the heat bar.
+        let top = HudLayout::below(self.hud_layout().top_left(), 1.5, HUD_LINE_HEIGHT);
+        for (i, text) in self.profile_display.iter().enumerate() {
+            let dest = HudLayout::below(top, i as f32, 20.0);
+            graphics::draw(ctx, text, dest, 0.0)?;
+        }
+        Ok(())
+    }
+
+    fn draw_ecs_stats(&self, ctx: &mut Context) -> GameResult<()> {
+        // Below the time, lined up on the right.
+        let layout = self.hud_layout();
+        for (i, text) in self.ecs_stats_display.iter().enumerate() {
+            let top = HudLayout::below(layout.top_right(text.width() as f32), 1.5, HUD_LINE_HEIGHT);
+            let dest = HudLayout::below(top, i as f32, 20.0);
+            graphics::draw(ctx, text, dest, 0.0)?;
+        }
+        Ok(())
+    }
+
+    fn draw_game_over(&self, ctx: &mut Context) -> GameResult<()> {
+        let width = self.window.width as f32;
+        let height = self.window.height as f32;
+        let old_color = graphics::get_color(ctx);
+        graphics::set_color(ctx, graphics::Color::new(0.0, 0.0, 0.0, 0.6))?;
+        let screen = graphics::Rect::new(0.0, 0.0, width, height);
+        graphics::rectangle(ctx, graphics::DrawMode::Fill, screen)?;
+        graphics::set_color(ctx, old_color)?;
+
+        let line_height = 30.0;
+        let top = (height - line_height * self.game_over_display.len() as f32) / 2.0;
+        for (i, text) in self.game_over_display.iter().enumerate() {
+            let dest = graphics::Point2::new(
+                (width - text.width() as f32) / 2.0,
+                top + line_height * i as f32,
             );
+            graphics::draw(ctx, text, dest, 0.0)?;
         }
+        Ok(())
     }
 
-    pub fn update_ui(&mut self, ctx: &mut Context) {
-        let score_str = format!("Score: {}", self.score);
-        let level_str = format!("Level: {}", self.level);
-        let score_text = graphics::Text::new(ctx, &score_str, &self.assets.font).unwrap();
-        let level_text = graphics::Text::new(ctx, &level_str, &self.assets.font).unwrap();
+    /// Write out the replay being recorded, if any. Only the first call
+    /// does anything.
+    pub fn save_recording(&mut self, ctx: &mut Context) {
+        if let Some(recording) = self.recording.take() {
+            match recording.save(ctx) {
+                Ok(()) => println!("Saved replay to {}", REPLAY_PATH),
+                Err(e) => println!("Could not save replay: {}", e),
+            }
+        }
+    }
 
-        self.score_display = score_text;
-        self.level_display = level_text;
+    /// Save the game in progress so it can be continued next time.
+    /// Finished games and replays aren't saved.
+    pub fn save_game(&mut self, ctx: &mut Context) {
+        if self.sim.is_game_over() || self.recording.is_some() || self.playback.is_some() {
+            return;
+        }
+        match SavedGame::capture(&self.sim, self.elapsed).save(ctx) {
+            Ok(()) => println!("Saved game to {}", SAVEGAME_PATH),
+            Err(e) => println!("Could not save game: {}", e),
+        }
     }
 }
 
+/// Fixed updates per second, unless the config says otherwise.
+pub const DESIRED_FPS: u32 = 60;
+
+/// The timestep handed to the game systems for one fixed update of
+/// `real_seconds`. Updates still come at a fixed real-time rate; slow
+/// motion just makes each one cover less game time.
+pub fn scaled_dt(real_seconds: f32, time_scale: f32) -> f32 {
+    real_seconds * time_scale
+}
+
+/// The background colors the levels cycle through. They're kept dark so
+/// the sprites still stand out.
+pub const LEVEL_BACKGROUNDS: [(u8, u8, u8); 5] = [
+    (0, 0, 0),
+    (8, 8, 36),
+    (28, 6, 28),
+    (6, 26, 18),
+    (32, 18, 4),
+];
+
+/// The background color for `level`. After the last color in
+/// `LEVEL_BACKGROUNDS` it starts again from the first.
+pub fn level_background(level: i32) -> graphics::Color {
+    let index = level.max(0) as usize % LEVEL_BACKGROUNDS.len();
+    LEVEL_BACKGROUNDS[index].into()
+}
+
 /// **********************************************************************
 /// Now we implement the `EventHandler` trait from `ggez::event`, which provides
 /// ggez with callbacks for updating and drawing our game, as well as
@@ -139,73 +437,60 @@ impl MainState {
 /// **********************************************************************
 impl EventHandler for MainState {
     fn update(&mut self, ctx: &mut Context) -> GameResult<()> {
-        const DESIRED_FPS: u32 = 60;
-
-        while timer::check_update_time(ctx, DESIRED_FPS) {
-            let seconds = 1.0 / (DESIRED_FPS as f32);
-
-            // Update the player state based on the user input.
-            let mut new_shots = Ecs::empty();
-            self.system
-                .components_mut::<Player>()
-                .for_each(|(_, mut player)| {
-                    player.player_handle_input(&self.system, &self.input, seconds);
-                    player.try_fire(
-                        &self.system,
-                        &mut new_shots,
-                        &self.input,
-                        &self.assets,
-                        seconds,
-                    );
-                });
-            self.system.merge(new_shots);
-
-            // Update the physics for all actors.
-            self.system
-                .components_mut::<Physics>()
-                .for_each(|(_, mut component)| {
-                    component.update_actor_position(&self.system, seconds);
-                    component.wrap_actor_position(
-                        &self.system,
-                        self.screen_width as f32,
-                        self.screen_height as f32,
-                    )
-                });
-
-            // Update the timers for shots.
-            self.system
-                .components_mut::<ShotLifetime>()
-                .for_each(|(_, mut shot)| {
-                    shot.handle_shot_timer(seconds);
-                });
-
-            // Handle the results of things moving:
-            // collision detection, object death, and if
-            // we have killed all the rocks in the level,
-            // spawn more of them.
-            self.system.components_ref::<Collider>()
-                .for_each(|(_, collider)| {
-                    collider.check_for_collisions(&self.system, &self.assets);
-                });
-
-            self.clear_dead_stuff();
-
-            self.check_for_level_respawn();
+        let fps = self.sim.config.target_fps();
+
+        while timer::check_update_time(ctx, fps) {
+            let real_seconds = self.sim.config.timestep();
+            let seconds = scaled_dt(real_seconds, self.time_scale);
+
+            // Nothing moves or gets recorded while paused, and the
+            // engine goes quiet until thrust starts again.
+            if !self.sim.is_playing() {
+                self.assets.thrust_sound.stop();
+                self.input.forget_thrust();
+                continue;
+            }
+
+            // During playback, the recording drives the game instead
+            // of the keyboard.
+            self.input.begin_frame();
+            let input = match &mut self.playback {
+                Some(playback) => playback.next_input().cloned().unwrap_or_default(),
+                None => self.input.clone(),
+            };
+            if let Some(recording) = &mut self.recording {
+                recording.record(&input);
+            }
+            self.assets.update_thrust_sound(&input);
+
+            self.sim.step(seconds, &input, Some(&self.assets));
+            self.update_timer(ctx, real_seconds);
+
+            if let Some(transform) = self.sim.system.try_borrow::<Transform>(self.sim.player) {
+                let field = (self.sim.width, self.sim.height);
+                self.camera.follow(transform.pos, field, seconds);
+            }
+            self.camera.add_trauma(self.sim.take_trauma());
+            self.camera.update(seconds);
+
+            for (_, mut popup) in self.sim.system.components_mut::<ScorePopup>() {
+                popup.build_text(ctx, &self.assets)?;
+            }
+            self.sim
+                .level_banner
+                .build_text(ctx, &self.assets, self.sim.level)?;
 
             // Using a gui_dirty flag here is a little
             // messy but fine here.
-            if self.gui_dirty {
+            if self.sim.gui_dirty {
                 self.update_ui(ctx);
-                self.gui_dirty = false;
+                self.sim.gui_dirty = false;
             }
 
-            // Finally we check for our end state.
-            // I want to have a nice death screen eventually,
-            // but for now we just quit.
-            let player_health: Health = self.system.get(self.player).unwrap();
-            if player_health.health <= 0.0 {
-                println!("Game over!");
-                let _ = ctx.quit();
+            // Finally we check for our end state. The world stops,
+            // but stays drawn behind the final stats.
+            if self.sim.state == GameState::GameOver {
+                self.end_game(ctx)?;
             }
         }
 
@@ -213,23 +498,78 @@ impl EventHandler for MainState {
     }
 
     fn draw(&mut self, ctx: &mut Context) -> GameResult<()> {
+        let background = level_background(self.sim.level);
+        if background != self.background {
+            graphics::set_background_color(ctx, background);
+            self.background = background;
+        }
+
         // Our drawing is quite simple.
         // Just clear the screen...
         graphics::clear(ctx);
+        let start = self.sim.profiler.start();
 
         // Loop over all objects drawing them...
-        let coords = (self.screen_width, self.screen_height);
-        for (_, sprite) in self.system.components_ref::<Sprite>() {
+        let coords = (self.window.width, self.window.height);
+        // Near a seam of a wrapping field, the far side shows through.
+        let seams = self.sim.config.wrap_mode.collision_field(self.sim.width, self.sim.height);
+        for (_, sprite) in self.sim.system.components_ref::<Sprite>() {
+            if !sprite.is_valid(&self.sim.system) || !sprite.is_visible(&self.sim.system) {
+                continue;
+            }
             sprite
-                .draw_actor(&self.assets, ctx, &self.system, coords)
+                .draw_actor(&self.assets, ctx, &self.sim.system, &self.camera, coords, seams)
                 .unwrap();
         }
+        for (_, popup) in self.sim.system.components_ref::<ScorePopup>() {
+            if !popup.transform.is_valid(&self.sim.system) {
+                continue;
+            }
+            popup.draw_popup(ctx, &self.sim.system, &self.camera, coords, seams)?;
+        }
+        draw_offscreen_indicators(ctx, &self.sim.system, &self.camera, coords, seams)?;
+        if self.sim.config.wrap_mode == WrapMode::Torus {
+            let field = (self.sim.width, self.sim.height);
+            let edges = wrap_warnings(&self.sim.system, self.sim.player, field);
+            draw_wrap_warnings(ctx, &edges, &self.camera, coords, field, self.elapsed)?;
+        }
 
         // And draw the GUI elements in the right places.
-        let level_dest = graphics::Point2::new(10.0, 10.0);
-        let score_dest = graphics::Point2::new(200.0, 10.0);
+        let layout = self.hud_layout();
+        let level_dest = layout.top_left();
+        let score_dest = layout.top_center(self.score_display.width() as f32);
+        let time_dest = layout.top_right(self.time_display.width() as f32);
         graphics::draw(ctx, &self.level_display, level_dest, 0.0)?;
         graphics::draw(ctx, &self.score_display, score_dest, 0.0)?;
+        graphics::draw(ctx, &self.time_display, time_dest, 0.0)?;
+        self.draw_heat_bar(ctx)?;
+        let field = (self.sim.width, self.sim.height);
+        draw_minimap(ctx, &self.sim.system, field, coords)?;
+        self.sim.level_banner.draw(ctx, self.window.width, self.window.height)?;
+        let overlay = match self.sim.state {
+            GameState::Playing => None,
+            GameState::GameOver => {
+                self.draw_game_over(ctx)?;
+                None
+            }
+            GameState::Paused => Some(&self.pause_display),
+            GameState::ConfirmQuit => Some(&self.quit_display),
+        };
+        if let Some(text) = overlay {
+            let dest = layout.center(text.width() as f32, text.height() as f32);
+            graphics::draw(ctx, text, dest, 0.0)?;
+        }
+        self.sim.profiler.stop("draw", start);
+
+        if self.sim.profiler.is_enabled() {
+            self.sim.profiler.end_frame();
+            self.update_profile_display(ctx)?;
+            self.draw_profile(ctx)?;
+        }
+        if self.show_ecs_stats {
+            self.update_ecs_stats_display(ctx)?;
+            self.draw_ecs_stats(ctx)?;
+        }
 
         // Then we flip the screen...
         graphics::present(ctx);
@@ -253,9 +593,11 @@ impl EventHandler for MainState {
             }
             Keycode::Left => {
                 self.input.xaxis = -1.0;
+                self.input.aim = None;
             }
             Keycode::Right => {
                 self.input.xaxis = 1.0;
+                self.input.aim = None;
             }
             Keycode::Space => {
                 self.input.fire = true;
@@ -265,11 +607,96 @@ impl EventHandler for MainState {
                 img.encode(ctx, graphics::ImageFormat::Png, "/screenshot.png")
                     .expect("Could not save screenshot");
             }
-            Keycode::Escape => ctx.quit().unwrap(),
+            Keycode::F5 => match self.assets.reload(ctx) {
+                Ok(()) => {
+                    self.assets.set_volume(self.sim.config.master_volume);
+                    println!("Reloaded assets");
+                }
+                Err(e) => println!("Could not reload assets: {}", e),
+            },
+            Keycode::F11 => {
+                if let Err(e) = self.toggle_fullscreen(ctx) {
+                    println!("Could not switch fullscreen: {}", e);
+                }
+            }
+            Keycode::Minus => {
+                self.camera.zoom_by(1.0 / ZOOM_STEP);
+            }
+            Keycode::Equals => {
+                self.camera.zoom_by(ZOOM_STEP);
+            }
+            Keycode::F3 => {
+                self.sim.profiler.toggle();
+                self.profile_display.clear();
+            }
+            Keycode::F4 => {
+                self.show_ecs_stats = !self.show_ecs_stats;
+                self.ecs_stats = None;
+                self.ecs_stats_display.clear();
+            }
+            Keycode::Return => {
+                self.sim.toggle_pause();
+            }
+            Keycode::R => {
+                if self.sim.state == GameState::GameOver {
+                    self.restart(ctx).unwrap();
+                }
+            }
+            Keycode::Escape => {
+                if self.sim.state == GameState::GameOver {
+                    ctx.quit().unwrap();
+                } else if self.sim.state == GameState::ConfirmQuit {
+                    self.sim.answer_quit(false);
+                } else {
+                    self.sim.request_quit();
+                }
+            }
+            Keycode::Y => {
+                // Saving happens in `quit_event`.
+                if self.sim.answer_quit(true) {
+                    ctx.quit().unwrap();
+                }
+            }
+            Keycode::N => {
+                self.sim.answer_quit(false);
+            }
             _ => (), // Do nothing
         }
     }
 
+    fn mouse_motion_event(
+        &mut self,
+        _ctx: &mut Context,
+        _state: MouseState,
+        x: i32,
+        y: i32,
+        _xrel: i32,
+        _yrel: i32,
+    ) {
+        let cursor = graphics::Point2::new(x as f32, y as f32);
+        let target = self
+            .camera
+            .screen_to_world(self.window.width, self.window.height, cursor);
+        if let Some(transform) = self.sim.system.try_borrow::<Transform>(self.sim.player) {
+            let offset = target - transform.pos;
+            self.input.aim = Some(offset.x.atan2(offset.y));
+        }
+    }
+
+    fn resize_event(&mut self, ctx: &mut Context, width: u32, height: u32) {
+        self.resize(ctx, width, height).unwrap();
+    }
+
+    fn focus_event(&mut self, _ctx: &mut Context, gained: bool) {
+        self.sim.focus_changed(gained);
+    }
+
+    fn quit_event(&mut self, ctx: &mut Context) -> bool {
+        self.save_game(ctx);
+        self.save_recording(ctx);
+        false
+    }
+
     fn key_up_event(&mut self, _ctx: &mut Context, keycode: Keycode, _keymod: Mod, _repeat: bool) {
         match keycode {
             Keycode::Up => {
@@ -285,3 +712,52 @@ impl EventHandler for MainState {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ggez::graphics::Vector2;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::super::components::Physics;
+
+    // How far the player drifts in one real-time update at `time_scale`.
+    fn drift(time_scale: f32) -> f32 {
+        let mut sim = Simulation::new(
+            Difficulty::Normal,
+            GameConfig::default(),
+            StdRng::from_seed([7; 32]),
+            640.0,
+            480.0,
+        );
+        let player = sim.player;
+        sim.system.borrow_mut::<Physics>(player).unwrap().velocity = Vector2::new(60.0, 0.0);
+        let start = sim.system.get::<Transform>(player).unwrap().pos;
+
+        sim.step(scaled_dt(1.0 / 60.0, time_scale), &InputState::default(), None);
+
+        let end = sim.system.get::<Transform>(player).unwrap().pos;
+        (end - start).norm()
+    }
+
+    #[test]
+    fn test_half_time_scale_moves_half_as_far() {
+        let normal = drift(1.0);
+        let slow = drift(0.5);
+
+        assert!(normal > 0.0);
+        assert!((slow - normal * 0.5).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_level_backgrounds_cycle() {
+        let len = LEVEL_BACKGROUNDS.len() as i32;
+        assert_eq!(level_background(0), graphics::Color::from_rgb(0, 0, 0));
+        assert_ne!(level_background(1), level_background(0));
+        for level in 0..len {
+            assert_eq!(level_background(level), level_background(level + len));
+            assert_eq!(level_background(level), LEVEL_BACKGROUNDS[level as usize].into());
+        }
+    }
+}
@@ -0,0 +1,127 @@
+use ggez::graphics::{Point2, Vector2};
+
+/// The gap between the HUD and the edges of the screen, in pixels.
+pub const HUD_MARGIN: f32 = 10.0;
+/// The height of a line of HUD text, in pixels.
+pub const HUD_LINE_HEIGHT: f32 = 30.0;
+
+/// *********************************************************************
+/// Places HUD elements against the edges of the screen rather than at
+/// fixed pixels, so they stay in their corners whatever size the window
+/// is. Each anchor takes the element's size and returns where its
+/// top-left corner goes, `HUD_MARGIN` in from the edges.
+/// **********************************************************************
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct HudLayout {
+    pub screen_width: f32,
+    pub screen_height: f32,
+}
+
+impl HudLayout {
+    pub fn new(screen_width: u32, screen_height: u32) -> HudLayout {
+        HudLayout {
+            screen_width: screen_width as f32,
+            screen_height: screen_height as f32,
+        }
+    }
+
+    pub fn top_left(&self) -> Point2 {
+        Point2::new(HUD_MARGIN, HUD_MARGIN)
+    }
+
+    pub fn top_center(&self, width: f32) -> Point2 {
+        Point2::new((self.screen_width - width) / 2.0, HUD_MARGIN)
+    }
+
+    pub fn top_right(&self, width: f32) -> Point2 {
+        Point2::new(self.screen_width - width - HUD_MARGIN, HUD_MARGIN)
+    }
+
+    pub fn bottom_right(&self, width: f32, height: f32) -> Point2 {
+        Point2::new(
+            self.screen_width - width - HUD_MARGIN,
+            self.screen_height - height - HUD_MARGIN,
+        )
+    }
+
+    /// Centered on the screen, ignoring the margin.
+    pub fn center(&self, width: f32, height: f32) -> Point2 {
+        Point2::new(
+            (self.screen_width - width) / 2.0,
+            (self.screen_height - height) / 2.0,
+        )
+    }
+
+    /// `anchor` moved down by `rows` lines of `line_height` pixels, for
+    /// stacking elements under one another.
+    pub fn below(anchor: Point2, rows: f32, line_height: f32) -> Point2 {
+        anchor + Vector2::new(0.0, rows * line_height)
+    }
+}
+
+/// The window as the game last saw it: its size in pixels, and whether
+/// it's fullscreen. Kept up to date from resize events.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct WindowState {
+    pub width: u32,
+    pub height: u32,
+    pub fullscreen: bool,
+}
+
+impl WindowState {
+    pub fn new(width: u32, height: u32) -> WindowState {
+        WindowState {
+            width,
+            height,
+            fullscreen: false,
+        }
+    }
+
+    /// Flip between windowed and fullscreen, returning the new mode. The
+    /// size isn't known until the window has switched; pass it to
+    /// `resize` then.
+    pub fn toggle_fullscreen(&mut self) -> bool {
+        self.fullscreen = !self.fullscreen;
+        self.fullscreen
+    }
+
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.width = width;
+        self.height = height;
+    }
+
+    pub fn layout(&self) -> HudLayout {
+        HudLayout::new(self.width, self.height)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_top_right_is_flush_with_the_right_edge() {
+        let width = 80.0;
+        for &(screen_w, screen_h) in [(640, 480), (1280, 720)].iter() {
+            let layout = HudLayout::new(screen_w, screen_h);
+            let pos = layout.top_right(width);
+
+            assert_eq!(pos.x + width + HUD_MARGIN, screen_w as f32);
+            assert_eq!(pos.y, HUD_MARGIN);
+        }
+    }
+
+    #[test]
+    fn test_fullscreen_toggle_then_resize() {
+        let mut window = WindowState::new(640, 480);
+
+        assert!(window.toggle_fullscreen());
+        window.resize(1920, 1080);
+        assert!(window.fullscreen);
+        assert_eq!(window.layout(), HudLayout::new(1920, 1080));
+
+        assert!(!window.toggle_fullscreen());
+        window.resize(640, 480);
+        assert_eq!((window.width, window.height), (640, 480));
+    }
+}
@@ -0,0 +1,329 @@
+use ggez::graphics::{self, Point2, Vector2};
+use ggez::{Context, GameResult};
+
+use super::better_ecs::{Ecs, EntityId};
+use super::camera::Camera;
+use super::components::{Physics, Rock, Transform};
+
+/// Only the closest few off-screen rocks get an arrow, to avoid clutter.
+pub const MAX_INDICATORS: usize = 3;
+
+/// How far in from the screen edge the arrows are drawn, in pixels.
+pub const INDICATOR_MARGIN: f32 = 12.0;
+
+// Arrow sizes in pixels, for a rock just off the edge and one that
+// is INDICATOR_FAR_DISTANCE pixels or more away.
+const INDICATOR_NEAR_SIZE: f32 = 14.0;
+const INDICATOR_FAR_SIZE: f32 = 6.0;
+const INDICATOR_FAR_DISTANCE: f32 = 300.0;
+
+/// How far ahead, in seconds, to warn about a rock wrapping in.
+pub const WRAP_WARNING_TIME: f32 = 1.0;
+
+/// A rock wrapping in counts as coming at the player if it comes out
+/// within this many units of the player's row (or column), and the
+/// player is within `WRAP_WARNING_REACH` units of the edge it comes out of.
+pub const WRAP_WARNING_BAND: f32 = 60.0;
+pub const WRAP_WARNING_REACH: f32 = 200.0;
+
+// The thickness of the flashing bar on a warned edge, in pixels.
+const WRAP_WARNING_THICKNESS: f32 = 6.0;
+
+/// *********************************************************************
+/// An arrow on the edge of the screen pointing at something outside it.
+/// Everything is in screen coordinates, and `angle` is in radians with
+/// 0 pointing right and increasing clockwise (since screen Y points down).
+/// **********************************************************************
+#[derive(Debug, Clone, PartialEq)]
+pub struct EdgeArrow {
+    pub pos: Point2,
+    pub angle: f32,
+
+    /// How far outside the screen the target is.
+    pub distance: f32,
+}
+
+impl EdgeArrow {
+    /// The arrow for a target at `target`, or `None` if it's on screen.
+    pub fn new(screen_width: u32, screen_height: u32, target: Point2) -> Option<EdgeArrow> {
+        let width = screen_width as f32;
+        let height = screen_height as f32;
+        let dx_outside = (-target.x).max(target.x - width).max(0.0);
+        let dy_outside = (-target.y).max(target.y - height).max(0.0);
+        if dx_outside == 0.0 && dy_outside == 0.0 {
+            return None;
+        }
+
+        // Walk from the center of the screen toward the target until
+        // we hit the (inset) edge.
+        let center = Point2::new(width / 2.0, height / 2.0);
+        let offset = target - center;
+        let half_w = width / 2.0 - INDICATOR_MARGIN;
+        let half_h = height / 2.0 - INDICATOR_MARGIN;
+        let scale_x = if offset.x != 0.0 { half_w / offset.x.abs() } else { f32::INFINITY };
+        let scale_y = if offset.y != 0.0 { half_h / offset.y.abs() } else { f32::INFINITY };
+        let pos = center + offset * scale_x.min(scale_y);
+
+        Some(EdgeArrow {
+            pos,
+            angle: offset.y.atan2(offset.x),
+            distance: (dx_outside.powi(2) + dy_outside.powi(2)).sqrt(),
+        })
+    }
+
+    /// Arrows shrink as the target gets farther away.
+    pub fn size(&self) -> f32 {
+        let t = (self.distance / INDICATOR_FAR_DISTANCE).min(1.0);
+        INDICATOR_NEAR_SIZE + (INDICATOR_FAR_SIZE - INDICATOR_NEAR_SIZE) * t
+    }
+
+    pub fn draw(&self, ctx: &mut Context) -> GameResult<()> {
+        let size = self.size();
+        let forward = Vector2::new(self.angle.cos(), self.angle.sin());
+        let side = Vector2::new(-forward.y, forward.x);
+        let points = [
+            self.pos + forward * size,
+            self.pos - forward * (size / 2.0) + side * (size / 2.0),
+            self.pos - forward * (size / 2.0) - side * (size / 2.0),
+        ];
+        graphics::polygon(ctx, graphics::DrawMode::Fill, &points)
+    }
+}
+
+/// The arrows for the `MAX_INDICATORS` closest off-screen rocks. On a
+/// field of size `field` that wraps, each arrow points the shortest way
+/// round, so rocks seen across a seam don't get one.
+pub fn offscreen_rock_arrows(
+    system: &Ecs,
+    camera: &Camera,
+    coords: (u32, u32),
+    field: (f32, f32),
+) -> Vec<EdgeArrow> {
+    let (screen_w, screen_h) = coords;
+    let mut arrows: Vec<EdgeArrow> = system
+        .entities_with::<Rock>()
+        .into_iter()
+        .filter_map(|rock| {
+            let transform = system.borrow::<Transform>(rock).ok()?;
+            let pos = camera.nearest_copy(transform.pos, field);
+            let screen_pos = camera.world_to_screen(screen_w, screen_h, pos);
+            EdgeArrow::new(screen_w, screen_h, screen_pos)
+        })
+        .collect();
+    arrows.sort_by(|a, b| a.distance.total_cmp(&b.distance));
+    arrows.truncate(MAX_INDICATORS);
+    arrows
+}
+
+pub fn draw_offscreen_indicators(
+    ctx: &mut Context,
+    system: &Ecs,
+    camera: &Camera,
+    coords: (u32, u32),
+    field: (f32, f32),
+) -> GameResult<()> {
+    let old_color = graphics::get_color(ctx);
+    graphics::set_color(ctx, graphics::Color::new(1.0, 0.3, 0.3, 0.8))?;
+    for arrow in offscreen_rock_arrows(system, camera, coords, field) {
+        arrow.draw(ctx)?;
+    }
+    graphics::set_color(ctx, old_color)
+}
+
+/// An edge of the playing field, or of the screen.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FieldEdge {
+    Left,
+    Right,
+    Top,
+    Bottom,
+}
+
+/// The edge a rock at `pos` moving at `velocity` will wrap in through
+/// within `within` seconds, if it comes out close enough to `player_pos`
+/// to be a threat; see `WRAP_WARNING_BAND`. The field is `field` in
+/// size, centered on the origin, with Y pointing up.
+pub fn predict_wrap(
+    pos: Point2,
+    velocity: Vector2,
+    player_pos: Point2,
+    field: (f32, f32),
+    within: f32,
+) -> Option<FieldEdge> {
+    let half_w = field.0 / 2.0;
+    let half_h = field.1 / 2.0;
+    // Leaving through the positive side of an axis brings the rock back
+    // in through the negative side, and the other way around.
+    let crossing = |pos: f32, vel: f32, half: f32| -> Option<(f32, f32)> {
+        let (time, entry) = if vel > 0.0 {
+            ((half - pos) / vel, -half)
+        } else if vel < 0.0 {
+            ((-half - pos) / vel, half)
+        } else {
+            return None;
+        };
+        if time >= 0.0 && time <= within {
+            Some((time, entry))
+        } else {
+            None
+        }
+    };
+    let threatens = |entry: f32, player: f32, across: f32, player_across: f32| {
+        (player - entry).abs() <= WRAP_WARNING_REACH
+            && (across - player_across).abs() <= WRAP_WARNING_BAND
+    };
+
+    let mut warnings = Vec::new();
+    if let Some((time, entry)) = crossing(pos.x, velocity.x, half_w) {
+        let y = pos.y + velocity.y * time;
+        if threatens(entry, player_pos.x, y, player_pos.y) {
+            let edge = if entry < 0.0 { FieldEdge::Left } else { FieldEdge::Right };
+            warnings.push((time, edge));
+        }
+    }
+    if let Some((time, entry)) = crossing(pos.y, velocity.y, half_h) {
+        let x = pos.x + velocity.x * time;
+        if threatens(entry, player_pos.y, x, player_pos.x) {
+            let edge = if entry < 0.0 { FieldEdge::Bottom } else { FieldEdge::Top };
+            warnings.push((time, edge));
+        }
+    }
+    warnings
+        .into_iter()
+        .min_by(|a, b| a.0.total_cmp(&b.0))
+        .map(|(_, edge)| edge)
+}
+
+/// The edges some rock is about to wrap in through at `player`, each
+/// listed once.
+pub fn wrap_warnings(system: &Ecs, player: EntityId, field: (f32, f32)) -> Vec<FieldEdge> {
+    let player_pos = match system.try_borrow::<Transform>(player) {
+        Some(transform) => transform.pos,
+        None => return Vec::new(),
+    };
+    let mut edges = Vec::new();
+    for rock in system.entities_with::<Rock>() {
+        let transform = system.try_borrow::<Transform>(rock);
+        let physics = system.try_borrow::<Physics>(rock);
+        let (transform, physics) = match (transform, physics) {
+            (Some(transform), Some(physics)) => (transform, physics),
+            _ => continue,
+        };
+        let (pos, velocity) = (transform.pos, physics.velocity);
+        if let Some(edge) = predict_wrap(pos, velocity, player_pos, field, WRAP_WARNING_TIME) {
+            if !edges.contains(&edge) {
+                edges.push(edge);
+            }
+        }
+    }
+    edges
+}
+
+/// The bar to flash on screen for a rock wrapping in through `edge` of
+/// the playing field of size `field`: along the seam as `camera` sees it,
+/// on the side the rock comes out of. `None` if the seam isn't on screen.
+pub fn wrap_warning_bar(
+    edge: FieldEdge,
+    camera: &Camera,
+    coords: (u32, u32),
+    field: (f32, f32),
+) -> Option<graphics::Rect> {
+    let (width, height) = (coords.0 as f32, coords.1 as f32);
+    let thickness = WRAP_WARNING_THICKNESS;
+    let (half_w, half_h) = (field.0 / 2.0, field.1 / 2.0);
+    let seam = match edge {
+        FieldEdge::Left => Point2::new(-half_w, camera.center.y),
+        FieldEdge::Right => Point2::new(half_w, camera.center.y),
+        FieldEdge::Top => Point2::new(camera.center.x, half_h),
+        FieldEdge::Bottom => Point2::new(camera.center.x, -half_h),
+    };
+    let seam = camera.world_to_screen(coords.0, coords.1, camera.nearest_copy(seam, field));
+    let on_screen = match edge {
+        FieldEdge::Left | FieldEdge::Right => seam.x >= 0.0 && seam.x <= width,
+        FieldEdge::Top | FieldEdge::Bottom => seam.y >= 0.0 && seam.y <= height,
+    };
+    if !on_screen {
+        return None;
+    }
+    // Screen Y points down, so the field's top is toward the screen's top.
+    let bar = match edge {
+        FieldEdge::Left => graphics::Rect::new(seam.x, 0.0, thickness, height),
+        FieldEdge::Right => graphics::Rect::new(seam.x - thickness, 0.0, thickness, height),
+        FieldEdge::Top => graphics::Rect::new(0.0, seam.y, width, thickness),
+        FieldEdge::Bottom => graphics::Rect::new(0.0, seam.y - thickness, width, thickness),
+    };
+    Some(bar)
+}
+
+/// Flash a bar along each seam of the playing field of size `field` that
+/// a rock is about to wrap in through, pulsing with `time` in seconds.
+/// Seams out of view aren't flashed.
+pub fn draw_wrap_warnings(
+    ctx: &mut Context,
+    edges: &[FieldEdge],
+    camera: &Camera,
+    coords: (u32, u32),
+    field: (f32, f32),
+    time: f32,
+) -> GameResult<()> {
+    if edges.is_empty() {
+        return Ok(());
+    }
+    let alpha = 0.35 + 0.35 * (time * 12.0).sin().abs();
+    let old_color = graphics::get_color(ctx);
+    graphics::set_color(ctx, graphics::Color::new(1.0, 0.3, 0.3, alpha))?;
+    for &edge in edges {
+        if let Some(bar) = wrap_warning_bar(edge, camera, coords, field) {
+            graphics::rectangle(ctx, graphics::DrawMode::Fill, bar)?;
+        }
+    }
+    graphics::set_color(ctx, old_color)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_arrow_clamps_to_right_edge() {
+        assert_eq!(EdgeArrow::new(640, 480, Point2::new(320.0, 240.0)), None);
+
+        let arrow = EdgeArrow::new(640, 480, Point2::new(700.0, 240.0)).unwrap();
+
+        assert_eq!(arrow.pos, Point2::new(640.0 - INDICATOR_MARGIN, 240.0));
+        assert_eq!(arrow.angle, 0.0);
+        assert_eq!(arrow.distance, 60.0);
+    }
+
+    #[test]
+    fn test_wrap_warning_for_rock_heading_at_seam() {
+        let field = (640.0, 480.0);
+        let player_pos = Point2::new(-250.0, 0.0);
+        let rock_pos = Point2::new(300.0, 10.0);
+
+        let toward = predict_wrap(rock_pos, Vector2::new(100.0, 0.0), player_pos, field, 1.0);
+        assert_eq!(toward, Some(FieldEdge::Left));
+
+        let away = predict_wrap(rock_pos, Vector2::new(-100.0, 0.0), player_pos, field, 1.0);
+        assert_eq!(away, None);
+    }
+
+    #[test]
+    fn test_wrap_warning_bar_follows_seam() {
+        let field = (2000.0, 2000.0);
+        let mut camera = Camera::default();
+        // The seams are far off screen.
+        assert!(wrap_warning_bar(FieldEdge::Left, &camera, (640, 480), field).is_none());
+        assert!(wrap_warning_bar(FieldEdge::Top, &camera, (640, 480), field).is_none());
+
+        // 100 units right of the left seam, so it's 100 pixels left of center.
+        camera.center = Point2::new(-900.0, 0.0);
+        let left = wrap_warning_bar(FieldEdge::Left, &camera, (640, 480), field).unwrap();
+        assert_eq!(left.x, 220.0);
+        assert_eq!(left.h, 480.0);
+        // The right edge is the same seam, seen from the other side.
+        let right = wrap_warning_bar(FieldEdge::Right, &camera, (640, 480), field).unwrap();
+        assert_eq!(right.x + right.w, 220.0);
+        assert!(wrap_warning_bar(FieldEdge::Top, &camera, (640, 480), field).is_none());
+    }
+}
@@ -4,40 +4,62 @@
 
 #[macro_use]
 extern crate lazy_static;
+#[macro_use]
+extern crate serde_derive;
 extern crate ggez;
 extern crate rand;
+extern crate serde;
+extern crate serde_json;
+extern crate toml;
 
 use ggez::audio;
 use ggez::conf;
 use ggez::event;
 use ggez::graphics;
 use ggez::graphics::Point2;
-use ggez::{Context, ContextBuilder, GameResult};
+use ggez::{Context, ContextBuilder, GameError, GameResult};
 
+use std::cell::Cell;
+use std::collections::HashMap;
 use std::env;
+use std::fmt;
 use std::path;
+use std::time::{Duration, Instant};
 
+#[macro_use]
 mod better_ecs;
+mod camera;
 mod components;
+mod config;
+mod difficulty;
 mod event_loop;
+mod hud;
+mod indicators;
+mod minimap;
 mod prefabs;
+mod profiler;
+mod records;
+mod replay;
+mod savegame;
+mod simulation;
+mod systems;
 mod util;
 mod vec;
 
 use self::components::ActorType;
+use self::difficulty::Difficulty;
 use self::event_loop::MainState;
+use self::replay::ReplayMode;
 
 pub const MAX_PHYSICS_VEL: f32 = 250.0;
 
 /// *********************************************************************
-/// Now we define our Actor's.
-/// An Actor is anything in the game world.
-/// We're not *quite* making a real entity-component system but it's
-/// pretty close.  For a more complicated game you would want a
-/// real ECS, but for this it's enough to say that all our game objects
-/// contain pretty much the same data.
+/// Everything in the game world is an entity in the ECS (`better_ecs`).
+/// The components live in `components`, and `prefabs` has the functions
+/// that put them together into players, rocks, and shots.
 /// **********************************************************************
 
+// The maximum rock speed, before difficulty scaling.
 pub const MAX_ROCK_VEL: f32 = 50.0;
 
 /// *********************************************************************
@@ -51,7 +73,6 @@ pub const MAX_ROCK_VEL: f32 = 50.0;
 /// **********************************************************************
 
 pub const SHOT_SPEED: f32 = 200.0;
-pub const SHOT_ANG_VEL: f32 = 0.1;
 
 /// Translates the world coordinate system, which
 /// has Y pointing up and the origin at the center,
@@ -65,6 +86,16 @@ pub fn world_to_screen_coords(screen_width: u32, screen_height: u32, point: Poin
     Point2::new(x, y)
 }
 
+/// The inverse of `world_to_screen_coords`: translates a point on
+/// the screen, such as the mouse position, back into world space.
+pub fn screen_to_world_coords(screen_width: u32, screen_height: u32, point: Point2) -> Point2 {
+    let width = screen_width as f32;
+    let height = screen_height as f32;
+    let x = point.x - width / 2.0;
+    let y = height / 2.0 - point.y;
+    Point2::new(x, y)
+}
+
 /// **********************************************************************
 /// So that was the real meat of our game.  Now we just need a structure
 /// to contain the images, sounds, etc. that we need to hang on to; this
@@ -72,53 +103,271 @@ pub fn world_to_screen_coords(screen_width: u32, screen_height: u32, point: Poin
 /// just hard-coded.
 /// **********************************************************************
 
+/// The rock images. Each rock picks one when it spawns, so the field
+/// doesn't look like one rock copied over and over.
+pub const ROCK_IMAGES: [&str; 3] = ["/rock.png", "/rock2.png", "/rock3.png"];
+
 pub struct Assets {
-    player_image: graphics::Image,
-    shot_image: graphics::Image,
-    rock_image: graphics::Image,
+    sprites: SpriteTable<Vec<graphics::Image>>,
     font: graphics::Font,
     shot_sound: audio::Source,
     hit_sound: audio::Source,
+    hit_cooldown: SoundCooldown,
+    // Loops for as long as the player thrusts.
+    thrust_sound: audio::Source,
+}
+
+/// Images for each kind of actor. New kinds register their art at load
+/// time; any kind without art gets the placeholder. With a list of images
+/// per kind, each actor can pick one of them; see `SpriteTable::variant`.
+pub struct SpriteTable<I> {
+    by_kind: HashMap<ActorType, I>,
+    placeholder: I,
+}
+
+impl<I> SpriteTable<I> {
+    pub fn new(placeholder: I) -> Self {
+        SpriteTable {
+            by_kind: HashMap::new(),
+            placeholder,
+        }
+    }
+
+    pub fn register(&mut self, kind: ActorType, image: I) {
+        self.by_kind.insert(kind, image);
+    }
+
+    pub fn get(&self, kind: &ActorType) -> Option<&I> {
+        self.by_kind.get(kind)
+    }
+
+    pub fn get_or_placeholder(&self, kind: &ActorType) -> &I {
+        self.get(kind).unwrap_or(&self.placeholder)
+    }
+}
+
+impl<I> SpriteTable<Vec<I>> {
+    /// Image number `variant` for `kind`. A variant past the end of the
+    /// list gets the first image, and a kind with no images gets the
+    /// placeholder's first.
+    pub fn variant(&self, kind: &ActorType, variant: usize) -> &I {
+        let images = match self.get(kind) {
+            Some(images) if !images.is_empty() => images,
+            _ => &self.placeholder,
+        };
+        images.get(variant).unwrap_or(&images[0])
+    }
+}
+
+/// The shortest gap between two plays of the hit sound. When a pile of
+/// rocks dies in one frame, one boom is plenty.
+pub const HIT_SOUND_COOLDOWN: Duration = Duration::from_millis(50);
+
+/// Keeps a sound from playing more often than once per `interval`.
+/// Plays asked for sooner than that are dropped.
+pub struct SoundCooldown {
+    interval: Duration,
+    last_played: Cell<Option<Instant>>,
+}
+
+impl SoundCooldown {
+    pub fn new(interval: Duration) -> Self {
+        SoundCooldown {
+            interval,
+            last_played: Cell::new(None),
+        }
+    }
+
+    /// Whether the sound may play at `now`. If it may, the cooldown
+    /// starts over from `now`.
+    pub fn try_play(&self, now: Instant) -> bool {
+        match self.last_played.get() {
+            Some(last) if now.duration_since(last) < self.interval => false,
+            _ => {
+                self.last_played.set(Some(now));
+                true
+            }
+        }
+    }
+}
+
+/// A resource that failed to load, and why.
+#[derive(Debug)]
+pub struct AssetError {
+    pub path: String,
+    pub cause: GameError,
+}
+
+impl fmt::Display for AssetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "failed to load {}: {}", self.path, self.cause)
+    }
+}
+
+impl From<AssetError> for GameError {
+    fn from(e: AssetError) -> GameError {
+        GameError::ResourceLoadError(e.to_string())
+    }
+}
+
+/// Run `load` on `path`, tagging any error with the path.
+fn load_asset<T, F>(path: &str, load: F) -> Result<T, AssetError>
+where
+    F: FnOnce(&str) -> GameResult<T>,
+{
+    load(path).map_err(|cause| AssetError {
+        path: path.to_owned(),
+        cause,
+    })
+}
+
+/// The size of the sprites made up for missing art, in pixels.
+pub const PLACEHOLDER_SIZE: u16 = 16;
+
+/// A square of `color`, drawn in place of missing art.
+fn placeholder_image(
+    ctx: &mut Context,
+    color: graphics::Color,
+) -> Result<graphics::Image, AssetError> {
+    load_asset("placeholder", |_| graphics::Image::solid(ctx, PLACEHOLDER_SIZE, color))
+}
+
+/// `loaded`, or if it failed to load from `path`, `placeholder()` instead,
+/// after saying why.
+fn or_placeholder<T, F>(path: &str, loaded: GameResult<T>, placeholder: F) -> Result<T, AssetError>
+where
+    F: FnOnce() -> Result<T, AssetError>,
+{
+    loaded.or_else(|e| {
+        println!("Could not load {}: {}; drawing a placeholder", path, e);
+        placeholder()
+    })
+}
+
+/// Load the images at `paths` for `kind` of actor. Any that can't be
+/// loaded are drawn as a square in the kind's `placeholder_color`, so the
+/// game still runs before the art is in.
+fn load_sprites(
+    ctx: &mut Context,
+    paths: &[&str],
+    kind: &ActorType,
+) -> Result<Vec<graphics::Image>, AssetError> {
+    let color = graphics::Color::from(kind.placeholder_color());
+    paths
+        .iter()
+        .map(|path| {
+            let loaded = graphics::Image::new(ctx, path);
+            or_placeholder(path, loaded, || placeholder_image(ctx, color))
+        })
+        .collect()
+}
+
+/// Swap `loaded` into `slot` if it loaded, or leave `slot` alone if not.
+fn replace_if_loaded<T, E>(slot: &mut T, loaded: Result<T, E>) -> Result<(), E> {
+    *slot = loaded?;
+    Ok(())
 }
 
 impl Assets {
-    pub fn new(ctx: &mut Context) -> GameResult<Assets> {
-        let player_image = graphics::Image::new(ctx, "/player.png")?;
-        let shot_image = graphics::Image::new(ctx, "/shot.png")?;
-        let rock_image = graphics::Image::new(ctx, "/rock.png")?;
-        let font = graphics::Font::new(ctx, "/DejaVuSerif.ttf", 18)?;
-
-        let shot_sound = audio::Source::new(ctx, "/pew.ogg")?;
-        let hit_sound = audio::Source::new(ctx, "/boom.ogg")?;
+    pub fn new(ctx: &mut Context) -> Result<Assets, AssetError> {
+        // Drawn for any actor without art of its own.
+        let placeholder = placeholder_image(ctx, graphics::Color::new(1.0, 0.0, 1.0, 1.0))?;
+        let mut sprites = SpriteTable::new(vec![placeholder]);
+
+        let player = load_sprites(ctx, &["/player.png"], &ActorType::Player)?;
+        let shot = load_sprites(ctx, &["/shot.png"], &ActorType::Shot)?;
+        // Bosses share the rock art, but stand out if it's missing.
+        let rock = load_sprites(ctx, &ROCK_IMAGES, &ActorType::Rock)?;
+        let boss = load_sprites(ctx, &ROCK_IMAGES, &ActorType::Boss)?;
+        sprites.register(ActorType::Player, player);
+        sprites.register(ActorType::Shot, shot);
+        sprites.register(ActorType::Rock, rock);
+        sprites.register(ActorType::Boss, boss);
+        let font = load_asset("/DejaVuSerif.ttf", |p| graphics::Font::new(ctx, p, 18))?;
+
+        let shot_sound = load_asset("/pew.ogg", |p| audio::Source::new(ctx, p))?;
+        let hit_sound = load_asset("/boom.ogg", |p| audio::Source::new(ctx, p))?;
+        let mut thrust_sound = load_asset("/thrust.wav", |p| audio::Source::new(ctx, p))?;
+        thrust_sound.set_repeat(true);
         Ok(Assets {
-            player_image,
-            shot_image,
-            rock_image,
+            sprites,
             font,
             shot_sound,
             hit_sound,
+            hit_cooldown: SoundCooldown::new(HIT_SOUND_COOLDOWN),
+            thrust_sound,
         })
     }
 
-    pub fn actor_image(&self, tag: &ActorType) -> &graphics::Image {
-        match &tag {
-            ActorType::Player => &self.player_image,
-            ActorType::Rock => &self.rock_image,
-            ActorType::Shot => &self.shot_image,
+    /// Set the volume of every sound, from 0 to 1.
+    pub fn set_volume(&mut self, volume: f32) {
+        self.shot_sound.set_volume(volume);
+        self.hit_sound.set_volume(volume);
+        self.thrust_sound.set_volume(volume);
+    }
+
+    /// Play the hit sound, unless it played within `HIT_SOUND_COOLDOWN`.
+    pub fn play_hit(&self) {
+        if self.hit_cooldown.try_play(Instant::now()) {
+            self.hit_sound.play().unwrap();
+        }
+    }
+
+    /// Start or stop the engine loop when thrust starts or stops. Holding
+    /// thrust doesn't restart it.
+    pub fn update_thrust_sound(&self, input: &InputState) {
+        if input.thrust_started {
+            self.thrust_sound.play().unwrap();
+        } else if input.thrust_stopped {
+            self.thrust_sound.stop();
         }
     }
+
+    /// Load everything again from disk, e.g. after editing the art. Missing
+    /// sprites get placeholders, as on the first load; if anything else
+    /// fails to load, the old assets are all kept.
+    pub fn reload(&mut self, ctx: &mut Context) -> Result<(), AssetError> {
+        replace_if_loaded(self, Assets::new(ctx))
+    }
+
+    /// Image number `variant` for `tag`; see `SpriteTable::variant`.
+    pub fn actor_image(&self, tag: &ActorType, variant: usize) -> &graphics::Image {
+        self.sprites.variant(tag, variant)
+    }
 }
 
 /// **********************************************************************
 /// The `InputState` is exactly what it sounds like, it just keeps track of
 /// the user's input state so that we turn keyboard events into something
 /// state-based and device-independent.
+///
+/// The key handlers only set the raw levels. `begin_frame` then works
+/// out which buttons were pressed or released since the last frame.
 /// **********************************************************************
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct InputState {
     xaxis: f32,
     yaxis: f32,
     fire: bool,
+
+    #[serde(default)]
+    fire_pressed: bool,
+    #[serde(default)]
+    fire_released: bool,
+    #[serde(default)]
+    last_fire: bool,
+
+    #[serde(default)]
+    thrust_started: bool,
+    #[serde(default)]
+    thrust_stopped: bool,
+    #[serde(default)]
+    last_thrust: bool,
+
+    /// The facing the mouse is aiming at, if it's been moved since the
+    /// arrow keys were last used.
+    #[serde(default)]
+    aim: Option<f32>,
 }
 
 impl Default for InputState {
@@ -127,20 +376,59 @@ impl Default for InputState {
             xaxis: 0.0,
             yaxis: 0.0,
             fire: false,
+            fire_pressed: false,
+            fire_released: false,
+            last_fire: false,
+            thrust_started: false,
+            thrust_stopped: false,
+            last_thrust: false,
+            aim: None,
         }
     }
 }
 
+impl InputState {
+    /// Update the press and release edges against last frame's levels.
+    /// Call once per frame, before anything reads the input.
+    pub fn begin_frame(&mut self) {
+        self.fire_pressed = self.fire && !self.last_fire;
+        self.fire_released = !self.fire && self.last_fire;
+        self.last_fire = self.fire;
+
+        let thrust = self.yaxis > 0.0;
+        self.thrust_started = thrust && !self.last_thrust;
+        self.thrust_stopped = !thrust && self.last_thrust;
+        self.last_thrust = thrust;
+    }
+
+    /// Forget that thrust was held, for when the engine sound is cut off
+    /// by a pause. If it's still held once play resumes, that counts as
+    /// starting it again.
+    pub fn forget_thrust(&mut self) {
+        self.thrust_started = false;
+        self.thrust_stopped = false;
+        self.last_thrust = false;
+    }
+}
+
 /// **********************************************************************
 /// A couple of utility functions.
 /// **********************************************************************
 
-pub fn print_instructions() {
+pub fn print_instructions(difficulty: Difficulty) {
     println!();
     println!("Welcome to ASTROBLASTO!");
     println!();
     println!("How to play:");
     println!("L/R arrow keys rotate your ship, up thrusts, space bar fires");
+    println!("Or aim with the mouse, and the ship turns to face it");
+    println!("Hold space to charge a bigger shot, enter pauses, F3 shows timings");
+    println!("F4 shows how many entities and components are alive");
+    println!("F11 toggles fullscreen, and - and = zoom out and in");
+    println!();
+    println!("Difficulty: {:?}", difficulty);
+    println!("(Pass easy, normal, or hard on the command line to change it.)");
+    println!("Pass --record to save a replay, or --replay to watch the last one.");
     println!();
 }
 
@@ -150,6 +438,21 @@ pub fn print_instructions() {
 /// **********************************************************************
 
 pub fn main() {
+    let mut difficulty = Difficulty::default();
+    let mut replay_mode = ReplayMode::Off;
+    for arg in env::args().skip(1) {
+        match arg.as_str() {
+            "--record" => replay_mode = ReplayMode::Record,
+            "--replay" => replay_mode = ReplayMode::Playback,
+            _ => {
+                difficulty = arg.parse().unwrap_or_else(|e| {
+                    println!("{}; using the default.", e);
+                    Difficulty::default()
+                })
+            }
+        }
+    }
+
     let mut cb = ContextBuilder::new("astroblasto", "ggez")
         .window_setup(conf::WindowSetup::default().title("Astroblasto!"))
         .window_mode(conf::WindowMode::default().dimensions(640, 480));
@@ -170,7 +473,7 @@ pub fn main() {
 
     let ctx = &mut cb.build().unwrap();
 
-    match MainState::new(ctx) {
+    match MainState::new(ctx, difficulty, replay_mode) {
         Err(e) => {
             println!("Could not load game!");
             println!("Error: {}", e);
@@ -185,3 +488,136 @@ pub fn main() {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_screen_to_world_inverts_world_to_screen() {
+        let (w, h) = (640, 480);
+        let points = [
+            Point2::origin(),
+            Point2::new(100.0, -50.0),
+            Point2::new(-320.0, 240.0),
+            Point2::new(12.5, 199.75),
+        ];
+        for &p in points.iter() {
+            let round_trip = screen_to_world_coords(w, h, world_to_screen_coords(w, h, p));
+            assert!((round_trip - p).norm() < 1e-4, "{:?} became {:?}", p, round_trip);
+        }
+    }
+
+    #[test]
+    fn test_fire_edges() {
+        let mut input = InputState::default();
+        let mut presses = Vec::new();
+        let mut releases = Vec::new();
+        for &fire in [false, true, true, true, false, false].iter() {
+            input.fire = fire;
+            input.begin_frame();
+            presses.push(input.fire_pressed);
+            releases.push(input.fire_released);
+        }
+
+        assert_eq!(presses, vec![false, true, false, false, false, false]);
+        assert_eq!(releases, vec![false, false, false, false, true, false]);
+    }
+
+    #[test]
+    fn test_thrust_loop_starts_and_stops_once() {
+        let mut input = InputState::default();
+        let mut starts = 0;
+        let mut stops = 0;
+        for &yaxis in [0.0, 1.0, 1.0, 1.0, 0.0, 0.0].iter() {
+            input.yaxis = yaxis;
+            input.begin_frame();
+            starts += input.thrust_started as i32;
+            stops += input.thrust_stopped as i32;
+        }
+
+        assert_eq!((starts, stops), (1, 1));
+    }
+
+    #[test]
+    fn test_thrust_held_through_pause_restarts_loop() {
+        let mut input = InputState { yaxis: 1.0, ..InputState::default() };
+        input.begin_frame();
+        assert!(input.thrust_started);
+
+        // Paused with thrust held: the loop is stopped, and play resumes
+        // with thrust still held.
+        input.forget_thrust();
+        input.begin_frame();
+        assert!(input.thrust_started);
+        input.begin_frame();
+        assert!(!input.thrust_started);
+    }
+
+    #[test]
+    fn test_asset_error_names_the_path() {
+        let result: Result<(), AssetError> = load_asset("/missing.png", |_| {
+            Err(GameError::ResourceLoadError("not found".to_owned()))
+        });
+        let message = result.unwrap_err().to_string();
+
+        assert!(message.contains("/missing.png"), "Unhelpful error: {}", message);
+    }
+
+    #[test]
+    fn test_missing_sprite_uses_placeholder() {
+        let mut sprites = SpriteTable::new("placeholder");
+        sprites.register(ActorType::Rock, "rock");
+
+        assert_eq!(sprites.get(&ActorType::Rock), Some(&"rock"));
+        assert_eq!(*sprites.get_or_placeholder(&ActorType::Rock), "rock");
+        assert_eq!(sprites.get(&ActorType::Shot), None);
+        assert_eq!(*sprites.get_or_placeholder(&ActorType::Shot), "placeholder");
+    }
+
+    #[test]
+    fn test_sprite_variants() {
+        let mut sprites = SpriteTable::new(vec!["placeholder"]);
+        sprites.register(ActorType::Rock, vec!["rock", "rock2"]);
+        sprites.register(ActorType::Boss, vec![]);
+
+        assert_eq!(*sprites.variant(&ActorType::Rock, 1), "rock2");
+        assert_eq!(*sprites.variant(&ActorType::Rock, 5), "rock");
+        assert_eq!(*sprites.variant(&ActorType::Boss, 0), "placeholder");
+        assert_eq!(*sprites.variant(&ActorType::Shot, 2), "placeholder");
+    }
+
+    #[test]
+    fn test_failed_reload_keeps_old_assets() {
+        let mut handle = "old";
+        let failed: Result<&str, AssetError> = load_asset("/rock.png", |_| {
+            Err(GameError::ResourceLoadError("not found".to_owned()))
+        });
+        assert!(replace_if_loaded(&mut handle, failed).is_err());
+        assert_eq!(handle, "old");
+
+        let loaded: Result<&str, AssetError> = load_asset("/rock.png", |_| Ok("new"));
+        assert!(replace_if_loaded(&mut handle, loaded).is_ok());
+        assert_eq!(handle, "new");
+    }
+
+    #[test]
+    fn test_sound_cooldown_drops_quick_repeats() {
+        let cooldown = SoundCooldown::new(HIT_SOUND_COOLDOWN);
+        let start = Instant::now();
+
+        assert!(cooldown.try_play(start));
+        assert!(!cooldown.try_play(start + HIT_SOUND_COOLDOWN / 2));
+        assert!(cooldown.try_play(start + HIT_SOUND_COOLDOWN));
+    }
+
+    #[test]
+    fn test_missing_art_gets_placeholder() {
+        let missing: GameResult<&str> = Err(GameError::ResourceLoadError("not found".to_owned()));
+        let drawn = or_placeholder("/rock.png", missing, || Ok("placeholder"));
+        assert_eq!(drawn.unwrap(), "placeholder");
+
+        let found = or_placeholder("/rock.png", Ok("rock"), || Ok("placeholder"));
+        assert_eq!(found.unwrap(), "rock");
+    }
+}
@@ -0,0 +1,79 @@
+use ggez::graphics::{self, Point2, Rect};
+use ggez::{Context, GameResult};
+
+use super::better_ecs::Ecs;
+use super::components::{ActorType, Tag, Transform};
+use super::hud::HudLayout;
+
+/// The minimap's size in pixels. It sits in the bottom-right corner.
+pub const MINIMAP_WIDTH: f32 = 120.0;
+pub const MINIMAP_HEIGHT: f32 = 90.0;
+
+const MINIMAP_DOT_SIZE: f32 = 3.0;
+
+/// Where the minimap goes on a screen of the given size.
+pub fn minimap_rect(screen_width: u32, screen_height: u32) -> Rect {
+    let layout = HudLayout::new(screen_width, screen_height);
+    let corner = layout.bottom_right(MINIMAP_WIDTH, MINIMAP_HEIGHT);
+    Rect::new(corner.x, corner.y, MINIMAP_WIDTH, MINIMAP_HEIGHT)
+}
+
+/// Scale a world position down onto the minimap. `field` is the size of
+/// the playing field; positions are wrapped into it first, so something
+/// straddling an edge always shows up on the same side.
+pub fn world_to_minimap(point: Point2, field: (f32, f32), map: Rect) -> Point2 {
+    let (field_w, field_h) = field;
+    // Fraction of the way across the field, wrapped into [0, 1).
+    let wrap = |f: f32| f - f.floor();
+    let fx = wrap(point.x / field_w + 0.5);
+    let fy = wrap(point.y / field_h + 0.5);
+    // World Y points up, screen Y points down.
+    Point2::new(map.x + fx * map.w, map.y + (1.0 - fy) * map.h)
+}
+
+/// Draw the minimap: the player as a green dot, and rocks and bosses as
+/// red ones. Shots are left off to cut the noise.
+pub fn draw_minimap(
+    ctx: &mut Context,
+    system: &Ecs,
+    field: (f32, f32),
+    coords: (u32, u32),
+) -> GameResult<()> {
+    let map = minimap_rect(coords.0, coords.1);
+    let dots = |kinds: &[ActorType]| -> Vec<Point2> {
+        kinds
+            .iter()
+            .flat_map(|kind| Tag::entities_of_type(system, kind.clone()))
+            .filter_map(|entity| system.try_borrow::<Transform>(entity))
+            .map(|transform| world_to_minimap(transform.pos, field, map))
+            .collect()
+    };
+    let player_dots = dots(&[ActorType::Player]);
+    let other_dots = dots(&[ActorType::Rock, ActorType::Boss]);
+
+    let old_color = graphics::get_color(ctx);
+    graphics::set_color(ctx, graphics::Color::new(1.0, 1.0, 1.0, 0.5))?;
+    graphics::rectangle(ctx, graphics::DrawMode::Line(1.0), map)?;
+    if !other_dots.is_empty() {
+        graphics::set_color(ctx, graphics::Color::new(1.0, 0.3, 0.3, 1.0))?;
+        graphics::points(ctx, &other_dots, MINIMAP_DOT_SIZE)?;
+    }
+    if !player_dots.is_empty() {
+        graphics::set_color(ctx, graphics::Color::new(0.3, 1.0, 0.3, 1.0))?;
+        graphics::points(ctx, &player_dots, MINIMAP_DOT_SIZE)?;
+    }
+    graphics::set_color(ctx, old_color)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_origin_maps_to_minimap_center() {
+        let map = minimap_rect(640, 480);
+        let center = world_to_minimap(Point2::origin(), (640.0, 480.0), map);
+
+        assert_eq!(center, Point2::new(map.x + map.w / 2.0, map.y + map.h / 2.0));
+    }
+}
@@ -1,89 +1,766 @@
-use ggez::graphics::Point2;
+use ggez::graphics::{Point2, Vector2};
+use ggez::nalgebra as na;
+use rand::Rng;
 
-use super::better_ecs::{Ecs, EntityId};
+use super::better_ecs::{Ecs, EcsError, EntityId};
 use super::components::{
-    ActorType, Collider, BoundingBox, Health, Physics, Player, Rock, ShotLifetime, Sprite, Tag, Transform,
+    ActorType, Blink, Collider, BoundingBox, Damage, Health, Invulnerable, Lifetime, Physics,
+    Player, PointValue, Projectile, Rock, RockSize, ScorePopup, Shot, Sprite, Tag, Transform,
 };
-use super::vec::{random_vec, vec_from_angle};
-use super::MAX_ROCK_VEL;
+use super::simulation::Score;
+use super::vec::{random_vec_in, vec_from_angle, wrapped_distance};
+use super::ROCK_IMAGES;
 
 pub const PLAYER_LIFE: f32 = 1.0;
 pub const SHOT_LIFE: f32 = 2.0;
 pub const ROCK_LIFE: f32 = 1.0;
+pub const BOSS_LIFE: f32 = 10.0;
+
+// Enough to destroy one ordinary rock.
+pub const SHOT_DAMAGE: f32 = ROCK_LIFE;
+pub const POPUP_LIFE: f32 = 1.0;
+
+/// How long the player is protected after spawning, and how fast it
+/// blinks meanwhile, in seconds.
+pub const SPAWN_PROTECTION_TIME: f32 = 2.0;
+pub const SPAWN_BLINK_PERIOD: f32 = 0.2;
 
 pub const PLAYER_BBOX: f32 = 12.0;
 pub const ROCK_BBOX: f32 = 12.0;
 pub const SHOT_BBOX: f32 = 6.0;
 
+/// The most rocks allowed in the world at once. Spawning and splitting
+/// silently drop any rocks past this.
+pub const MAX_ROCKS: usize = 40;
+
+/// Rocks in the first wave, before difficulty scaling. Each level adds one.
+pub const START_ROCKS: i32 = 5;
+
+/// How far from the player a new wave's rocks spawn.
+pub const ROCK_SPAWN_MIN_RADIUS: f32 = 100.0;
+pub const ROCK_SPAWN_MAX_RADIUS: f32 = 250.0;
+
+/// How wide a cone in front of the player a new wave leaves clear of
+/// rocks, in radians, so there's always a way out.
+pub const ESCAPE_CORRIDOR_ANGLE: f32 = std::f32::consts::PI / 3.0;
+
+// How far past the edge of the corridor a rock that has to be moved out
+// of it ends up, in radians.
+const CORRIDOR_MARGIN: f32 = 0.05;
+
+/// How far outside the field edge-spawned rocks start.
+pub const EDGE_SPAWN_MARGIN: f32 = 20.0;
+
+// How far an edge-spawned rock's heading may stray from straight in, in
+// radians.
+const EDGE_SPAWN_SPREAD: f32 = std::f32::consts::PI / 4.0;
+
+/// How close together scattered rocks, and the player, may start.
+pub const SCATTER_SPACING: f32 = 100.0;
+
+// How many random spots `poisson_disk_points` tries for each point before
+// giving up on it.
+const POISSON_TRIES: usize = 30;
+
+/// Where a new level's wave of rocks comes from.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SpawnPattern {
+    /// In a ring around the player; see `create_rocks`.
+    Ring,
+    /// Drifting in from the edges of the field; see `spawn_from_edges`.
+    Edges,
+    /// Spread evenly across the field; see `spawn_scattered`.
+    Scattered,
+}
+
+/// The slowest a new rock drifts, as a fraction of its maximum speed, so
+/// none of them sit nearly still.
+pub const ROCK_MIN_SPEED_FRACTION: f32 = 0.25;
+
+/// The number of fragments a rock breaks into when destroyed.
+pub const ROCK_SPLIT_COUNT: usize = 2;
+
+pub fn rock_points(size: RockSize) -> Score {
+    match size {
+        RockSize::Boss => 500,
+        RockSize::Large => 20,
+        RockSize::Medium => 50,
+        RockSize::Small => 100,
+    }
+}
+
+/// How big a rock of this size is drawn, relative to the rock image.
+pub fn rock_scale(size: RockSize) -> f32 {
+    match size {
+        RockSize::Boss => 2.5,
+        RockSize::Large => 1.0,
+        RockSize::Medium => 0.75,
+        RockSize::Small => 0.5,
+    }
+}
+
+/// The hit circle for a rock of this size, matching its drawn size.
+pub fn rock_bbox_size(size: RockSize) -> f32 {
+    ROCK_BBOX * rock_scale(size)
+}
+
+/// How much the camera shakes when a rock of this size is destroyed.
+pub fn rock_kill_trauma(size: RockSize) -> f32 {
+    match size {
+        RockSize::Boss => 0.6,
+        RockSize::Large => 0.3,
+        RockSize::Medium => 0.2,
+        RockSize::Small => 0.1,
+    }
+}
+
+pub fn rock_health(size: RockSize) -> f32 {
+    match size {
+        RockSize::Boss => BOSS_LIFE,
+        _ => ROCK_LIFE,
+    }
+}
+
+fn rock_actor_type(size: RockSize) -> ActorType {
+    match size {
+        RockSize::Boss => ActorType::Boss,
+        _ => ActorType::Rock,
+    }
+}
+
 /// *********************************************************************
 /// Now we have some constructor functions for different game objects.
+///
+/// Everything but the player can be spawned mid-game, so those return
+/// an error instead of panicking when the Ecs runs out of ids. The
+/// game just goes without that entity.
 /// **********************************************************************
 
-pub fn create_player(system: &mut Ecs) -> EntityId {
+pub fn create_player(system: &mut Ecs, shot_time: f32) -> EntityId {
     system.build_entity()
         .with(Tag::new(ActorType::Player))
         .with(Transform::default())
         .with1(Physics::new)
         .with2(Sprite::new)
-        .with1(|transform| BoundingBox::new(PLAYER_BBOX, transform))
+        .with1(|transform| BoundingBox::circle(PLAYER_BBOX, transform))
         .with(Health::new(PLAYER_LIFE))
-        .with2(Player::new)
+        .with2(|transform, physics| Player::new(transform, physics, shot_time))
+        .with2(Collider::new)
+        .with(Invulnerable::new(SPAWN_PROTECTION_TIME))
+        .with(Blink::new(SPAWN_BLINK_PERIOD))
         .build()
         .unwrap()
 }
 
-pub fn create_rock(system: &mut Ecs) -> EntityId {
-    system.build_entity()
-        .with(Tag::new(ActorType::Rock))
-        .with(Transform::default())
+/// Create a rock, drawn with the first rock image. `create_rocks` and the
+/// other functions that spawn rocks in play pick an image at random.
+pub fn create_rock(system: &mut Ecs, size: RockSize) -> Result<EntityId, EcsError> {
+    system.try_build_entity()?
+        .with(Tag::new(rock_actor_type(size)))
+        .with(Transform {
+            scale: rock_scale(size),
+            ..Transform::default()
+        })
         .with1(Physics::new)
         .with2(Sprite::new)
-        .with1(|transform| BoundingBox::new(ROCK_BBOX, transform))
-        .with(Health::new(ROCK_LIFE))
-        .with(Rock)
+        .with1(|transform| BoundingBox::circle(rock_bbox_size(size), transform))
+        .with(Health::new(rock_health(size)))
+        .with(Rock::new(size))
+        .with(PointValue::new(rock_points(size)))
         .with2(Collider::new)
         .build()
-        .unwrap()
 }
 
-pub fn create_shot(system: &mut Ecs) -> EntityId {
-    system.build_entity()
+pub fn create_shot(system: &mut Ecs) -> Result<EntityId, EcsError> {
+    system.try_build_entity()?
         .with(Tag::new(ActorType::Shot))
         .with(Transform::default())
         .with1(Physics::new)
         .with2(Sprite::new)
-        .with1(|transform| BoundingBox::new(SHOT_BBOX, transform))
-        .with(ShotLifetime::new(SHOT_LIFE))
+        .with1(|transform| BoundingBox::aabb(SHOT_BBOX, SHOT_BBOX, transform))
+        .with(Shot)
+        .with(Lifetime::new(SHOT_LIFE))
+        .with(Damage::new(SHOT_DAMAGE))
+        .with(Projectile::new(None))
+        .build()
+}
+
+pub fn create_score_popup(
+    system: &mut Ecs,
+    pos: Point2,
+    points: Score,
+) -> Result<EntityId, EcsError> {
+    system.try_build_entity()?
+        .with(Transform {
+            pos,
+            ..Transform::default()
+        })
+        .with1(|transform| ScorePopup::new(points, POPUP_LIFE, transform))
+        .with(Lifetime::new(POPUP_LIFE))
         .build()
-        .unwrap()
 }
 
+/// How many random positions `create_rocks` tries for each rock before
+/// giving up and clamping the last one into the playing field.
+pub const ROCK_SPAWN_TRIES: usize = 16;
+
 /// Create the given number of rocks.
 /// Makes sure that none of them are within the
-/// given exclusion zone (nominally the player)
-/// Note that this *could* create rocks outside the
-/// bounds of the playing field, so it should be
-/// called before `wrap_actor_position()` happens.
-pub fn create_rocks(
+/// given exclusion zone (nominally the player), and
+/// that all of them are inside the playing field of
+/// size `bounds`, centered on the origin.
+///
+/// Positions are picked by rejection sampling. If no good position turns
+/// up after `ROCK_SPAWN_TRIES` tries, the last one is clamped into the
+/// field instead.
+///
+/// All randomness is drawn from `rng`, so a seeded generator will
+/// always produce the same field.
+///
+/// No more than `MAX_ROCKS` rocks will exist afterwards; any extra rocks
+/// are not created. If the Ecs runs out of ids, the rocks made so far are
+/// all there is.
+#[allow(clippy::too_many_arguments)]
+pub fn create_rocks<R: Rng>(
     system: &mut Ecs,
+    rng: &mut R,
     num: i32,
+    bounds: (f32, f32),
     exclusion: Point2,
     min_radius: f32,
     max_radius: f32,
+    max_vel: f32,
 ) -> Vec<EntityId> {
-    assert!(max_radius > min_radius);
-    let new_rock = |_| {
-        let rock = create_rock(system);
-        let r_angle = rand::random::<f32>() * 2.0 * std::f32::consts::PI;
-        let r_distance = rand::random::<f32>() * (max_radius - min_radius) + min_radius;
+    let room = MAX_ROCKS.saturating_sub(system.count_components::<Rock>());
+    let num = (num.max(0) as usize).min(room);
+    let mut rocks = Vec::with_capacity(num);
+    for _ in 0..num {
+        let rock = match create_rock(system, RockSize::Large) {
+            Ok(rock) => rock,
+            Err(_) => break,
+        };
+        pick_rock_image(system, rng, rock);
+        place_rock(system, rng, rock, bounds, exclusion, min_radius, max_radius, max_vel);
+        rocks.push(rock);
+    }
+    rocks
+}
 
-        let mut transfrom = system.borrow_mut::<Transform>(rock).unwrap();
-        transfrom.pos = exclusion + vec_from_angle(r_angle) * r_distance;
+/// Create a boss rock, placed the same way as `create_rocks` places
+/// its rocks. Bosses ignore `MAX_ROCKS`.
+pub fn create_boss<R: Rng>(
+    system: &mut Ecs,
+    rng: &mut R,
+    bounds: (f32, f32),
+    exclusion: Point2,
+    min_radius: f32,
+    max_radius: f32,
+    max_vel: f32,
+) -> Result<EntityId, EcsError> {
+    let boss = create_rock(system, RockSize::Boss)?;
+    pick_rock_image(system, rng, boss);
+    place_rock(system, rng, boss, bounds, exclusion, min_radius, max_radius, max_vel);
+    Ok(boss)
+}
 
-        let mut physics = system.borrow_mut::<Physics>(rock).unwrap();
-        physics.velocity = random_vec(MAX_ROCK_VEL);
+/// A velocity for a new rock: any direction, at least
+/// `ROCK_MIN_SPEED_FRACTION` of `max_vel` and at most `max_vel`.
+fn random_rock_velocity<R: Rng>(rng: &mut R, max_vel: f32) -> Vector2 {
+    random_vec_in(rng, max_vel * ROCK_MIN_SPEED_FRACTION, max_vel)
+}
+
+/// Draw `rock` with one of the `ROCK_IMAGES`, chosen with `rng`.
+fn pick_rock_image<R: Rng>(system: &Ecs, rng: &mut R, rock: EntityId) {
+    system.borrow_mut::<Sprite>(rock).unwrap().variant = rng.gen_range(0, ROCK_IMAGES.len());
+}
+
+/// Give a freshly created rock a random position and velocity. See
+/// `create_rocks` for how the position is chosen. A `max_radius` below
+/// `min_radius` is taken to be `min_radius`.
+#[allow(clippy::too_many_arguments)]
+fn place_rock<R: Rng>(
+    system: &Ecs,
+    rng: &mut R,
+    rock: EntityId,
+    bounds: (f32, f32),
+    exclusion: Point2,
+    min_radius: f32,
+    max_radius: f32,
+    max_vel: f32,
+) {
+    let max_radius = max_radius.max(min_radius);
+    let half_w = bounds.0 / 2.0;
+    let half_h = bounds.1 / 2.0;
+    let in_bounds = |pos: Point2| pos.x.abs() <= half_w && pos.y.abs() <= half_h;
+
+    let mut pos = exclusion;
+    for _ in 0..ROCK_SPAWN_TRIES {
+        let r_angle = rng.gen::<f32>() * 2.0 * std::f32::consts::PI;
+        let r_distance = rng.gen::<f32>() * (max_radius - min_radius) + min_radius;
+        pos = exclusion + vec_from_angle(r_angle) * r_distance;
+        if in_bounds(pos) && na::distance(&pos, &exclusion) >= min_radius {
+            break;
+        }
+    }
+    pos.x = pos.x.max(-half_w).min(half_w);
+    pos.y = pos.y.max(-half_h).min(half_h);
+
+    let mut transfrom = system.borrow_mut::<Transform>(rock).unwrap();
+    transfrom.pos = pos;
+
+    let mut physics = system.borrow_mut::<Physics>(rock).unwrap();
+    physics.velocity = random_rock_velocity(rng, max_vel);
+}
+
+/// How far `pos` is turned from the direction `facing` as seen from
+/// `origin`, in radians from -pi to pi. Positive is clockwise, like
+/// `facing` itself.
+fn angle_off_facing(pos: Point2, origin: Point2, facing: f32) -> f32 {
+    use std::f32::consts::PI;
+    let offset = pos - origin;
+    let angle = offset.x.atan2(offset.y);
+    (angle - facing + PI).rem_euclid(2.0 * PI) - PI
+}
+
+/// Whether `pos` is inside the cone `clear_angle` radians wide that opens
+/// from `origin` toward `facing`.
+pub fn in_escape_corridor(pos: Point2, origin: Point2, facing: f32, clear_angle: f32) -> bool {
+    angle_off_facing(pos, origin, facing).abs() < clear_angle / 2.0
+}
+
+/// Move any of `rocks` out of the escape corridor: the cone `clear_angle`
+/// radians wide in front of a player at `player_pos` facing `facing`.
+/// Rocks in the way are re-placed the way `create_rocks` places them, up
+/// to `ROCK_SPAWN_TRIES` times; one that still lands in the corridor is
+/// swung around the player to just outside it, at the same distance.
+///
+/// Like `create_rocks`, this draws all its randomness from `rng`. A
+/// `clear_angle` of zero leaves everything where it is.
+#[allow(clippy::too_many_arguments)]
+pub fn clear_escape_corridor<R: Rng>(
+    system: &Ecs,
+    rng: &mut R,
+    rocks: &[EntityId],
+    bounds: (f32, f32),
+    player_pos: Point2,
+    facing: f32,
+    clear_angle: f32,
+    min_radius: f32,
+    max_radius: f32,
+    max_vel: f32,
+) {
+    let rock_pos = |rock: EntityId| system.borrow::<Transform>(rock).unwrap().pos;
+    for &rock in rocks {
+        for _ in 0..ROCK_SPAWN_TRIES {
+            if !in_escape_corridor(rock_pos(rock), player_pos, facing, clear_angle) {
+                break;
+            }
+            place_rock(system, rng, rock, bounds, player_pos, min_radius, max_radius, max_vel);
+        }
+
+        let pos = rock_pos(rock);
+        if in_escape_corridor(pos, player_pos, facing, clear_angle) {
+            let side = if angle_off_facing(pos, player_pos, facing) < 0.0 { -1.0 } else { 1.0 };
+            let angle = facing + side * (clear_angle / 2.0 + CORRIDOR_MARGIN);
+            let distance = na::distance(&pos, &player_pos);
+            system.borrow_mut::<Transform>(rock).unwrap().pos =
+                player_pos + vec_from_angle(angle) * distance;
+        }
+    }
+}
+
+/// Create the given number of rocks just outside random edges of the
+/// playing field of size `bounds`, each heading into the field at a
+/// random speed up to `max_vel`.
+///
+/// Like `create_rocks`, rocks past `MAX_ROCKS` or past the last id are
+/// not created.
+pub fn spawn_from_edges<R: Rng>(
+    system: &mut Ecs,
+    rng: &mut R,
+    num: i32,
+    bounds: (f32, f32),
+    max_vel: f32,
+) -> Vec<EntityId> {
+    use std::f32::consts::PI;
+
+    let half_w = bounds.0 / 2.0;
+    let half_h = bounds.1 / 2.0;
+    let room = MAX_ROCKS.saturating_sub(system.count_components::<Rock>());
+    let num = (num.max(0) as usize).min(room);
+    let mut rocks = Vec::with_capacity(num);
+    for _ in 0..num {
+        let rock = match create_rock(system, RockSize::Large) {
+            Ok(rock) => rock,
+            Err(_) => break,
+        };
+        pick_rock_image(system, rng, rock);
 
-        rock
+        // Each edge, and the heading that points straight in from it.
+        let along_x = rng.gen_range(-half_w, half_w);
+        let along_y = rng.gen_range(-half_h, half_h);
+        let (pos, inward) = match rng.gen_range(0, 4) {
+            0 => (Point2::new(along_x, half_h + EDGE_SPAWN_MARGIN), PI),
+            1 => (Point2::new(along_x, -half_h - EDGE_SPAWN_MARGIN), 0.0),
+            2 => (Point2::new(half_w + EDGE_SPAWN_MARGIN, along_y), -PI / 2.0),
+            _ => (Point2::new(-half_w - EDGE_SPAWN_MARGIN, along_y), PI / 2.0),
+        };
+        let heading = inward + rng.gen_range(-EDGE_SPAWN_SPREAD, EDGE_SPAWN_SPREAD);
+        // Not `gen_range`, which panics if a config sets `max_vel` to zero.
+        let fraction = ROCK_MIN_SPEED_FRACTION + rng.gen::<f32>() * (1.0 - ROCK_MIN_SPEED_FRACTION);
+        let speed = max_vel * fraction;
+
+        system.borrow_mut::<Transform>(rock).unwrap().pos = pos;
+        system.borrow_mut::<Physics>(rock).unwrap().velocity = vec_from_angle(heading) * speed;
+        rocks.push(rock);
+    }
+    rocks
+}
+
+/// Pick up to `num` random points on the wrapping playing field of size
+/// `bounds`, none of them closer than `min_distance` to each other or to
+/// any of `existing` (which aren't returned), and all of them `allowed`.
+/// This spreads them out much more evenly than picking points at random.
+///
+/// Each point gets `POISSON_TRIES` random spots to find room. Once one
+/// doesn't find any, the field is taken to be full, and the points found
+/// so far are all there is.
+pub fn poisson_disk_points<R, F>(
+    rng: &mut R,
+    num: usize,
+    min_distance: f32,
+    bounds: (f32, f32),
+    existing: &[Point2],
+    allowed: F,
+) -> Vec<Point2>
+where
+    R: Rng,
+    F: Fn(Point2) -> bool,
+{
+    let half_w = bounds.0 / 2.0;
+    let half_h = bounds.1 / 2.0;
+    let mut taken = existing.to_vec();
+    'points: for _ in 0..num {
+        for _ in 0..POISSON_TRIES {
+            let candidate =
+                Point2::new(rng.gen_range(-half_w, half_w), rng.gen_range(-half_h, half_h));
+            let has_room = allowed(candidate)
+                && taken.iter().all(|&other| {
+                    wrapped_distance(candidate, other, bounds.0, bounds.1) >= min_distance
+                });
+            if has_room {
+                taken.push(candidate);
+                continue 'points;
+            }
+        }
+        break;
+    }
+    taken.split_off(existing.len())
+}
+
+/// Create up to `num` rocks spread across the playing field of size
+/// `bounds` with `poisson_disk_points`, at least `SCATTER_SPACING` from
+/// each other and from the rocks already there, and at least
+/// `exclusion_radius` from `exclusion`. Each drifts off in a random
+/// direction at up to `max_vel`.
+///
+/// Fewer rocks are made if the field fills up. Like `create_rocks`, rocks
+/// past `MAX_ROCKS` or past the last id are not created.
+pub fn spawn_scattered<R: Rng>(
+    system: &mut Ecs,
+    rng: &mut R,
+    num: i32,
+    bounds: (f32, f32),
+    exclusion: Point2,
+    exclusion_radius: f32,
+    max_vel: f32,
+) -> Vec<EntityId> {
+    let room = MAX_ROCKS.saturating_sub(system.count_components::<Rock>());
+    let num = (num.max(0) as usize).min(room);
+    let existing: Vec<Point2> = system
+        .components_sorted::<Rock>()
+        .map(|id| system.get_parent(id).unwrap())
+        .filter_map(|rock| system.try_borrow::<Transform>(rock).map(|t| t.pos))
+        .collect();
+    let clear_of_exclusion = |pos: Point2| {
+        wrapped_distance(pos, exclusion, bounds.0, bounds.1) >= exclusion_radius
+    };
+    let points =
+        poisson_disk_points(rng, num, SCATTER_SPACING, bounds, &existing, clear_of_exclusion);
+
+    let mut rocks = Vec::with_capacity(points.len());
+    for pos in points {
+        let rock = match create_rock(system, RockSize::Large) {
+            Ok(rock) => rock,
+            Err(_) => break,
+        };
+        pick_rock_image(system, rng, rock);
+        system.borrow_mut::<Transform>(rock).unwrap().pos = pos;
+        system.borrow_mut::<Physics>(rock).unwrap().velocity = random_rock_velocity(rng, max_vel);
+        rocks.push(rock);
+    }
+    rocks
+}
+
+/// Break a destroyed rock of the given size into smaller fragments at
+/// `pos`, each drifting off in a random direction.
+///
+/// Small rocks don't split. Like `create_rocks`, fragments that would put
+/// the world over `MAX_ROCKS`, or that there are no ids left for, are dropped.
+pub fn split_rock<R: Rng>(
+    system: &mut Ecs,
+    rng: &mut R,
+    pos: Point2,
+    size: RockSize,
+    max_vel: f32,
+) -> Vec<EntityId> {
+    let fragment_size = match size.smaller() {
+        Some(fragment_size) => fragment_size,
+        None => return Vec::new(),
     };
-    (0..num).map(new_rock).collect()
+    let room = MAX_ROCKS.saturating_sub(system.count_components::<Rock>());
+
+    let mut fragments = Vec::with_capacity(ROCK_SPLIT_COUNT);
+    for _ in 0..ROCK_SPLIT_COUNT.min(room) {
+        let rock = match create_rock(system, fragment_size) {
+            Ok(rock) => rock,
+            Err(_) => break,
+        };
+        pick_rock_image(system, rng, rock);
+
+        let mut transform = system.borrow_mut::<Transform>(rock).unwrap();
+        transform.pos = pos;
+
+        let mut physics = system.borrow_mut::<Physics>(rock).unwrap();
+        physics.velocity = random_rock_velocity(rng, max_vel);
+
+        fragments.push(rock);
+    }
+    fragments
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::super::config::GameConfig;
+    use super::super::difficulty::Difficulty;
+
+    const BOUNDS: (f32, f32) = (640.0, 480.0);
+
+    fn rock_speeds(difficulty: Difficulty) -> Vec<f32> {
+        let mut ecs = Ecs::new();
+        let mut rng = StdRng::from_seed([7; 32]);
+        let rocks = create_rocks(
+            &mut ecs,
+            &mut rng,
+            5,
+            BOUNDS,
+            Point2::origin(),
+            100.0,
+            250.0,
+            difficulty.max_rock_vel(&GameConfig::default()),
+        );
+        rocks
+            .into_iter()
+            .map(|rock| ecs.borrow::<Physics>(rock).unwrap().velocity.norm())
+            .collect()
+    }
+
+    #[test]
+    fn test_rock_images_follow_the_seed() {
+        let images = || {
+            let mut ecs = Ecs::new();
+            let mut rng = StdRng::from_seed([7; 32]);
+            let origin = Point2::origin();
+            let rocks = create_rocks(&mut ecs, &mut rng, 10, BOUNDS, origin, 100.0, 250.0, 50.0);
+            rocks
+                .into_iter()
+                .map(|rock| ecs.borrow::<Sprite>(rock).unwrap().variant)
+                .collect::<Vec<_>>()
+        };
+
+        let first = images();
+        assert_eq!(first, images());
+        assert!(first.iter().all(|&variant| variant < ROCK_IMAGES.len()));
+        assert!(first.iter().any(|&variant| variant != first[0]), "Every rock looked the same.");
+    }
+
+    #[test]
+    fn test_create_rocks_clamps_to_cap() {
+        let mut ecs = Ecs::new();
+        let mut rng = StdRng::from_seed([7; 32]);
+        let near_cap = MAX_ROCKS as i32 - 2;
+        create_rocks(&mut ecs, &mut rng, near_cap, BOUNDS, Point2::origin(), 100.0, 250.0, 50.0);
+
+        let created = create_rocks(&mut ecs, &mut rng, 5, BOUNDS, Point2::origin(), 100.0, 250.0, 50.0);
+
+        assert_eq!(created.len(), 2);
+        assert_eq!(ecs.count_components::<Rock>(), MAX_ROCKS);
+    }
+
+    #[test]
+    fn test_rocks_spawn_outside_exclusion_zone() {
+        let mut ecs = Ecs::new();
+        let mut rng = StdRng::from_seed([7; 32]);
+        // Near a corner, so many candidate positions land off the field.
+        let exclusion = Point2::new(250.0, 180.0);
+        let min_radius = 100.0;
+        let rocks = create_rocks(&mut ecs, &mut rng, 30, BOUNDS, exclusion, min_radius, 250.0, 50.0);
+
+        for rock in rocks {
+            let pos = ecs.borrow::<Transform>(rock).unwrap().pos;
+            assert!(na::distance(&pos, &exclusion) >= min_radius, "Rock spawned at {:?}", pos);
+            assert!(pos.x.abs() <= BOUNDS.0 / 2.0 && pos.y.abs() <= BOUNDS.1 / 2.0);
+        }
+    }
+
+    #[test]
+    fn test_backwards_spawn_radii_dont_panic() {
+        let mut ecs = Ecs::new();
+        let mut rng = StdRng::from_seed([7; 32]);
+        let origin = Point2::origin();
+        let rocks = create_rocks(&mut ecs, &mut rng, 5, BOUNDS, origin, 100.0, 50.0, 50.0);
+        let boss = create_boss(&mut ecs, &mut rng, BOUNDS, origin, 100.0, 50.0, 50.0).unwrap();
+
+        for rock in rocks.into_iter().chain(Some(boss)) {
+            let pos = ecs.borrow::<Transform>(rock).unwrap().pos;
+            assert!((na::distance(&pos, &origin) - 100.0).abs() < 1e-3, "Rock at {:?}", pos);
+        }
+    }
+
+    #[test]
+    fn test_wave_leaves_escape_corridor_clear() {
+        let mut ecs = Ecs::new();
+        let mut rng = StdRng::from_seed([7; 32]);
+        let player_pos = Point2::new(-40.0, 30.0);
+        let facing = 2.0;
+        let clear_angle = 1.2;
+        let rocks = create_rocks(&mut ecs, &mut rng, 30, BOUNDS, player_pos, 100.0, 250.0, 50.0);
+
+        clear_escape_corridor(
+            &ecs, &mut rng, &rocks, BOUNDS, player_pos, facing, clear_angle, 100.0, 250.0, 50.0,
+        );
+
+        let forward = vec_from_angle(facing);
+        for rock in rocks {
+            let offset = ecs.borrow::<Transform>(rock).unwrap().pos - player_pos;
+            let angle = (offset.dot(&forward) / offset.norm()).acos();
+            assert!(angle >= clear_angle / 2.0, "Rock {} radians off the facing.", angle);
+        }
+    }
+
+    #[test]
+    fn test_split_at_cap_creates_nothing() {
+        let mut ecs = Ecs::new();
+        let mut rng = StdRng::from_seed([7; 32]);
+        create_rocks(
+            &mut ecs,
+            &mut rng,
+            MAX_ROCKS as i32,
+            BOUNDS,
+            Point2::origin(),
+            100.0,
+            250.0,
+            50.0,
+        );
+
+        let fragments = split_rock(&mut ecs, &mut rng, Point2::origin(), RockSize::Large, 50.0);
+
+        assert!(fragments.is_empty());
+        assert_eq!(ecs.count_components::<Rock>(), MAX_ROCKS);
+    }
+
+    #[test]
+    fn test_rock_point_values() {
+        let mut ecs = Ecs::new();
+        let large = create_rock(&mut ecs, RockSize::Large).unwrap();
+        let small = create_rock(&mut ecs, RockSize::Small).unwrap();
+
+        assert_eq!(ecs.borrow::<PointValue>(large).unwrap().points, 20);
+        assert_eq!(ecs.borrow::<PointValue>(small).unwrap().points, 100);
+    }
+
+    #[test]
+    fn test_score_popup_expires() {
+        let mut ecs = Ecs::new();
+        let popup = create_score_popup(&mut ecs, Point2::new(10.0, 20.0), 3).unwrap();
+
+        let points = ecs.borrow::<ScorePopup>(popup).unwrap().points;
+        assert_eq!(points, 3);
+
+        let mut steps = 0;
+        while !ecs.borrow::<Lifetime>(popup).unwrap().is_over() {
+            ecs.borrow_mut::<Lifetime>(popup).unwrap().update(0.25);
+            ecs.borrow_mut::<ScorePopup>(popup).unwrap().update_popup(&ecs, 0.25);
+            steps += 1;
+        }
+
+        assert_eq!(steps, (POPUP_LIFE / 0.25) as i32);
+        let transform: Transform = ecs.get(popup).unwrap();
+        assert!(transform.pos.y > 20.0, "Popup didn't drift upward.");
+    }
+
+    #[test]
+    fn test_hard_rocks_faster_than_easy() {
+        let easy = rock_speeds(Difficulty::Easy);
+        let hard = rock_speeds(Difficulty::Hard);
+
+        for (easy_speed, hard_speed) in easy.iter().zip(hard.iter()) {
+            assert!(hard_speed > easy_speed, "Hard rock wasn't faster than the easy one.");
+        }
+    }
+
+    #[test]
+    fn test_poisson_disk_points_keep_their_distance() {
+        let mut rng = StdRng::from_seed([7; 32]);
+        let player = Point2::origin();
+        let points = poisson_disk_points(&mut rng, 10, 100.0, BOUNDS, &[player], |_| true);
+        assert_eq!(points.len(), 10);
+
+        let mut all = points.clone();
+        all.push(player);
+        for (i, &a) in all.iter().enumerate() {
+            for &b in &all[i + 1..] {
+                let distance = wrapped_distance(a, b, BOUNDS.0, BOUNDS.1);
+                assert!(distance >= 100.0, "{:?} and {:?} are {} apart", a, b, distance);
+            }
+        }
+
+        // Asking for more than fits gets as many as fit.
+        let crowded = poisson_disk_points(&mut rng, 1000, 100.0, BOUNDS, &[], |_| true);
+        assert!(!crowded.is_empty() && crowded.len() < 1000);
+    }
+
+    #[test]
+    fn test_edge_spawns_start_outside_heading_in() {
+        let mut ecs = Ecs::new();
+        let mut rng = StdRng::from_seed([7; 32]);
+        let rocks = spawn_from_edges(&mut ecs, &mut rng, 20, BOUNDS, 100.0);
+        assert_eq!(rocks.len(), 20);
+
+        let (half_w, half_h) = (BOUNDS.0 / 2.0, BOUNDS.1 / 2.0);
+        for rock in rocks {
+            let pos = ecs.get::<Transform>(rock).unwrap().pos;
+            let velocity = ecs.get::<Physics>(rock).unwrap().velocity;
+            assert!(pos.x.abs() > half_w || pos.y.abs() > half_h, "Rock spawned at {:?}", pos);
+            if pos.x > half_w {
+                assert!(velocity.x < 0.0);
+            } else if pos.x < -half_w {
+                assert!(velocity.x > 0.0);
+            } else if pos.y > half_h {
+                assert!(velocity.y < 0.0);
+            } else {
+                assert!(velocity.y > 0.0);
+            }
+        }
+
+        let still = spawn_from_edges(&mut ecs, &mut rng, 2, BOUNDS, 0.0);
+        for rock in still {
+            assert_eq!(ecs.get::<Physics>(rock).unwrap().velocity, Vector2::zeros());
+        }
+    }
 }
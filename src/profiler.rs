@@ -0,0 +1,115 @@
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use ggez::timer;
+
+/// How many frames the profile overlay averages over before refreshing.
+pub const PROFILE_FRAMES: u32 = 60;
+
+/// *********************************************************************
+/// Adds up how long each part of a frame takes, by label, so the update
+/// loop can be profiled in-game. Wrap a system in `start` and `stop`,
+/// and call `end_frame` once per frame to get per-frame averages.
+///
+/// While the profiler is off, `start` doesn't even read the clock, so
+/// the calls can stay in the loop for good.
+/// **********************************************************************
+#[derive(Debug, Clone, Default)]
+pub struct Profiler {
+    enabled: bool,
+    totals: BTreeMap<&'static str, Duration>,
+    frames: u32,
+}
+
+impl Profiler {
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Turn profiling on or off, starting over from nothing either way.
+    pub fn toggle(&mut self) {
+        self.enabled = !self.enabled;
+        self.reset();
+    }
+
+    /// Start timing something, if the profiler is on. Hand the result to
+    /// `stop` when it's done.
+    pub fn start(&self) -> Option<Instant> {
+        if self.enabled {
+            Some(Instant::now())
+        } else {
+            None
+        }
+    }
+
+    /// Finish timing something begun with `start`.
+    pub fn stop(&mut self, label: &'static str, start: Option<Instant>) {
+        if let Some(start) = start {
+            self.record(label, start.elapsed());
+        }
+    }
+
+    /// Add `duration` to the total for `label`.
+    pub fn record(&mut self, label: &'static str, duration: Duration) {
+        *self.totals.entry(label).or_insert_with(Duration::default) += duration;
+    }
+
+    pub fn end_frame(&mut self) {
+        self.frames += 1;
+    }
+
+    pub fn frames(&self) -> u32 {
+        self.frames
+    }
+
+    /// The time spent on `label` per frame, on average.
+    pub fn average(&self, label: &str) -> Option<Duration> {
+        self.totals.get(label).map(|&total| total / self.frames.max(1))
+    }
+
+    /// One line per label with its average, in milliseconds.
+    pub fn report(&self) -> Vec<String> {
+        self.totals
+            .keys()
+            .map(|&label| {
+                let average = self.average(label).unwrap();
+                format!("{}: {:.2} ms", label, timer::duration_to_f64(average) * 1000.0)
+            })
+            .collect()
+    }
+
+    pub fn reset(&mut self) {
+        self.totals.clear();
+        self.frames = 0;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_durations_accumulate_per_label() {
+        let mut profiler = Profiler::default();
+        profiler.record("physics", Duration::from_millis(3));
+        profiler.end_frame();
+        profiler.record("physics", Duration::from_millis(5));
+        profiler.record("draw", Duration::from_millis(2));
+        profiler.end_frame();
+
+        assert_eq!(profiler.frames(), 2);
+        assert_eq!(profiler.average("physics"), Some(Duration::from_millis(4)));
+        assert_eq!(profiler.average("draw"), Some(Duration::from_millis(1)));
+        assert_eq!(profiler.average("collision"), None);
+    }
+
+    #[test]
+    fn test_disabled_profiler_measures_nothing() {
+        let mut profiler = Profiler::default();
+        let start = profiler.start();
+        profiler.stop("physics", start);
+
+        assert_eq!(start, None);
+        assert_eq!(profiler.average("physics"), None);
+    }
+}
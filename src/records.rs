@@ -0,0 +1,65 @@
+use ggez::{Context, GameError, GameResult};
+use std::io;
+
+pub const RECORDS_PATH: &str = "/records.json";
+
+/// Format a number of seconds as `MM:SS`, rounding down. Minutes keep
+/// counting past 99 rather than wrapping.
+pub fn format_time(seconds: f32) -> String {
+    let whole = seconds.max(0.0) as u32;
+    format!("{:02}:{:02}", whole / 60, whole % 60)
+}
+
+/// *********************************************************************
+/// The player's personal bests, kept between sessions in
+/// `/records.json`.
+/// **********************************************************************
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Records {
+    /// The longest anyone has survived, in seconds.
+    #[serde(default)]
+    pub best_time: f32,
+}
+
+impl Records {
+    /// Record a finished game's survival time. Returns true if it's a
+    /// new best.
+    pub fn add_time(&mut self, time: f32) -> bool {
+        if time > self.best_time {
+            self.best_time = time;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn save(&self, ctx: &mut Context) -> GameResult<()> {
+        let file = ctx.filesystem.create(RECORDS_PATH)?;
+        serde_json::to_writer(file, self).map_err(io::Error::from)?;
+        Ok(())
+    }
+
+    /// Load the saved records. Having none yet is not an error.
+    pub fn load(ctx: &mut Context) -> GameResult<Records> {
+        if !ctx.filesystem.exists(RECORDS_PATH) {
+            return Ok(Records::default());
+        }
+        let file = ctx.filesystem.open(RECORDS_PATH)?;
+        serde_json::from_reader(file).map_err(|e| {
+            GameError::ResourceLoadError(format!("Failed to load {}: {}", RECORDS_PATH, e))
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_format_time() {
+        assert_eq!(format_time(0.0), "00:00");
+        assert_eq!(format_time(9.9), "00:09");
+        assert_eq!(format_time(83.0), "01:23");
+        assert_eq!(format_time(754.5), "12:34");
+    }
+}
@@ -0,0 +1,170 @@
+use ggez::{Context, GameError, GameResult};
+use std::io;
+
+use super::config::GameConfig;
+use super::difficulty::Difficulty;
+use super::InputState;
+
+pub const REPLAY_PATH: &str = "/replay.json";
+
+/// Whether the game is recording its input, playing back a recording,
+/// or neither.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ReplayMode {
+    Off,
+    Record,
+    Playback,
+}
+
+/// *********************************************************************
+/// A recording of a game: the RNG seed, difficulty, and tunables it
+/// started with, and the input for every simulation step. Feeding the
+/// same inputs into a simulation built from the same seed replays the
+/// game.
+/// **********************************************************************
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Replay {
+    pub seed: u64,
+    pub difficulty: Difficulty,
+    #[serde(default)]
+    pub config: GameConfig,
+    pub inputs: Vec<InputState>,
+}
+
+impl Replay {
+    pub fn new(seed: u64, difficulty: Difficulty, config: GameConfig) -> Self {
+        Replay {
+            seed,
+            difficulty,
+            config,
+            inputs: Vec::new(),
+        }
+    }
+
+    /// Record the input for one simulation step.
+    pub fn record(&mut self, input: &InputState) {
+        self.inputs.push(input.clone());
+    }
+
+    pub fn save(&self, ctx: &mut Context) -> GameResult<()> {
+        let file = ctx.filesystem.create(REPLAY_PATH)?;
+        serde_json::to_writer(file, self).map_err(io::Error::from)?;
+        Ok(())
+    }
+
+    pub fn load(ctx: &mut Context) -> GameResult<Replay> {
+        let file = ctx.filesystem.open(REPLAY_PATH)?;
+        serde_json::from_reader(file).map_err(|e| {
+            GameError::ResourceLoadError(format!("Failed to load {}: {}", REPLAY_PATH, e))
+        })
+    }
+}
+
+/// Feeds a recorded replay back into the simulation, one step at a time.
+pub struct Playback {
+    replay: Replay,
+    next: usize,
+}
+
+impl Playback {
+    pub fn new(replay: Replay) -> Self {
+        Playback { replay, next: 0 }
+    }
+
+    /// The input for the next step, or `None` once the recording runs out.
+    pub fn next_input(&mut self) -> Option<&InputState> {
+        let input = self.replay.inputs.get(self.next)?;
+        self.next += 1;
+        Some(input)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ggez::graphics::Point2;
+    use super::super::components::{Health, Invulnerable, Rock, Transform};
+    use super::super::simulation::{Score, Simulation};
+    use super::super::util::seeded_rng;
+
+    const DT: f32 = 1.0 / 60.0;
+
+    fn set_input(input: &mut InputState, step: usize) {
+        input.xaxis = if step % 90 < 30 { 1.0 } else { 0.0 };
+        input.yaxis = if step % 40 < 25 { 1.0 } else { 0.0 };
+        input.fire = step % 20 < 10;
+        input.begin_frame();
+    }
+
+    fn new_sim(replay: &Replay) -> Simulation {
+        let mut sim = Simulation::new(
+            replay.difficulty,
+            replay.config.clone(),
+            seeded_rng(replay.seed),
+            640.0,
+            480.0,
+        );
+        // Keep the player alive, so the whole session gets played.
+        sim.system.insert(sim.player, Invulnerable::new(1000.0)).unwrap();
+        sim
+    }
+
+    /// Step `sim`, first killing several rocks at once now and then, so
+    /// that their scoring and splitting all land in the same step.
+    fn step_with_kills(sim: &mut Simulation, step: usize, input: &InputState) {
+        if step == 100 || step == 200 {
+            let rocks: Vec<_> = sim.system.components_sorted::<Rock>().collect();
+            for (i, id) in rocks.into_iter().enumerate() {
+                // Every other rock the first time, so the second time
+                // rocks of different sizes (and points) die together.
+                if step == 200 || i % 2 == 0 {
+                    let rock = sim.system.get_parent(id).unwrap();
+                    sim.system.borrow_mut::<Health>(rock).unwrap().health = 0.0;
+                }
+            }
+        }
+        sim.step(DT, input, None);
+    }
+
+    /// What should come out the same on every run: the score, the player,
+    /// and every rock's position.
+    fn outcome(sim: &Simulation) -> (Score, (Point2, f32), Vec<Point2>) {
+        let player: Transform = sim.system.get(sim.player).unwrap();
+        let rocks = sim
+            .system
+            .components_sorted::<Rock>()
+            .map(|id| sim.system.get::<Transform>(sim.system.get_parent(id).unwrap()).unwrap().pos)
+            .collect();
+        (sim.score, (player.pos, player.facing), rocks)
+    }
+
+    #[test]
+    fn test_replay_reproduces_session() {
+        let mut replay = Replay::new(1234, Difficulty::Normal, GameConfig::default());
+        let mut sim = new_sim(&replay);
+        let mut input = InputState::default();
+        for step in 0..300 {
+            set_input(&mut input, step);
+            replay.record(&input);
+            step_with_kills(&mut sim, step, &input);
+        }
+        let recorded = outcome(&sim);
+        assert!(recorded.0 > 0, "Nothing was scored.");
+        assert!(!recorded.2.is_empty(), "No rocks were left to compare.");
+
+        // Round trip through the file format too.
+        let json = serde_json::to_string(&replay).unwrap();
+        let loaded: Replay = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded, replay);
+
+        let mut replayed_sim = new_sim(&loaded);
+        let mut playback = Playback::new(loaded);
+        let mut step = 0;
+        while let Some(input) = playback.next_input() {
+            step_with_kills(&mut replayed_sim, step, input);
+            step += 1;
+        }
+
+        assert_eq!(outcome(&replayed_sim), recorded);
+    }
+}
@@ -0,0 +1,231 @@
+use ggez::graphics::{Point2, Vector2};
+use ggez::{Context, GameError, GameResult};
+use rand::Rng;
+use std::io;
+
+use super::better_ecs::{Ecs, EntityId};
+use super::components::{
+    Health, Physics, Player, Rock, RockSize, Sprite, Transform, Weapon, WeaponKind,
+};
+use super::config::GameConfig;
+use super::difficulty::Difficulty;
+use super::prefabs::create_rock;
+use super::simulation::{Score, Simulation};
+use super::util::seeded_rng;
+
+pub const SAVEGAME_PATH: &str = "/savegame.json";
+
+/// Where something is, which way it faces, and how it's moving.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SavedBody {
+    pub pos: (f32, f32),
+    pub facing: f32,
+    pub velocity: (f32, f32),
+    pub ang_vel: f32,
+    pub health: f32,
+}
+
+impl SavedBody {
+    fn capture(system: &Ecs, entity: EntityId) -> SavedBody {
+        let transform = system.borrow::<Transform>(entity).unwrap();
+        let physics = system.borrow::<Physics>(entity).unwrap();
+        SavedBody {
+            pos: (transform.pos.x, transform.pos.y),
+            facing: transform.facing,
+            velocity: (physics.velocity.x, physics.velocity.y),
+            ang_vel: physics.ang_vel,
+            health: system.borrow::<Health>(entity).unwrap().health,
+        }
+    }
+
+    fn restore(&self, system: &Ecs, entity: EntityId) {
+        let mut transform = system.borrow_mut::<Transform>(entity).unwrap();
+        transform.pos = Point2::new(self.pos.0, self.pos.1);
+        transform.facing = self.facing;
+        let mut physics = system.borrow_mut::<Physics>(entity).unwrap();
+        physics.velocity = Vector2::new(self.velocity.0, self.velocity.1);
+        physics.ang_vel = self.ang_vel;
+        system.borrow_mut::<Health>(entity).unwrap().health = self.health;
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SavedRock {
+    pub size: RockSize,
+    pub variant: usize,
+    pub body: SavedBody,
+}
+
+/// *********************************************************************
+/// A game in progress, saved to `/savegame.json` on quitting so it can
+/// be continued next time.
+///
+/// Only what lasts is kept: the score, the level, the player and the
+/// rocks. Shots and score popups are gone in a moment anyway, so they're
+/// dropped. Loading builds a fresh world from this with the prefabs, so
+/// entity ids start over; `Simulation::player` is pointed at the new
+/// player. The random generator can't be saved, so a seed for a fresh
+/// one is drawn from a copy of it instead; saving doesn't change what the
+/// game rolls next.
+/// **********************************************************************
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SavedGame {
+    pub difficulty: Difficulty,
+    pub config: GameConfig,
+    pub seed: u64,
+    pub field: (f32, f32),
+
+    pub score: Score,
+    pub level: i32,
+    pub level_time: f32,
+    /// Whether the level's wave was still to come.
+    pub wave_pending: bool,
+    /// Seconds played, for the survival time.
+    pub elapsed: f32,
+
+    pub player: SavedBody,
+    pub weapon: WeaponKind,
+    pub shots_fired: u32,
+    pub rocks_destroyed: u32,
+    pub rocks: Vec<SavedRock>,
+}
+
+impl SavedGame {
+    pub fn capture(sim: &Simulation, elapsed: f32) -> SavedGame {
+        let system = &sim.system;
+        let player = system.borrow::<Player>(sim.player).unwrap();
+        let rocks = system
+            .components_sorted::<Rock>()
+            .map(|id| {
+                let entity = system.get_parent(id).unwrap();
+                SavedRock {
+                    size: system.borrow::<Rock>(entity).unwrap().size,
+                    variant: system.borrow::<Sprite>(entity).unwrap().variant,
+                    body: SavedBody::capture(system, entity),
+                }
+            })
+            .collect();
+
+        SavedGame {
+            difficulty: sim.difficulty,
+            config: sim.config.clone(),
+            seed: sim.rng.clone().gen(),
+            field: (sim.width, sim.height),
+            score: sim.score,
+            level: sim.level,
+            level_time: sim.level_time,
+            wave_pending: sim.level_banner.is_wave_pending(),
+            elapsed,
+            player: SavedBody::capture(system, sim.player),
+            weapon: player.weapon.kind,
+            shots_fired: player.shots_fired,
+            rocks_destroyed: sim.rocks_destroyed,
+            rocks,
+        }
+    }
+
+    /// Build the saved game back up. The player gets a fresh spell of
+    /// spawn protection, to get their bearings.
+    pub fn restore(&self) -> Simulation {
+        let mut sim = Simulation::new(
+            self.difficulty,
+            self.config.clone(),
+            seeded_rng(self.seed),
+            self.field.0,
+            self.field.1,
+        );
+        sim.score = self.score;
+        sim.level = self.level;
+        sim.level_time = self.level_time;
+        sim.rocks_destroyed = self.rocks_destroyed;
+        if !self.wave_pending {
+            sim.level_banner = Default::default();
+        }
+
+        self.player.restore(&sim.system, sim.player);
+        {
+            let mut player = sim.system.borrow_mut::<Player>(sim.player).unwrap();
+            player.weapon = Weapon::new(self.weapon);
+            player.shots_fired = self.shots_fired;
+        }
+
+        for saved in &self.rocks {
+            // Out of ids in a brand new Ecs would be a bug.
+            let rock = create_rock(&mut sim.system, saved.size).unwrap();
+            saved.body.restore(&sim.system, rock);
+            sim.system.borrow_mut::<Sprite>(rock).unwrap().variant = saved.variant;
+        }
+        sim
+    }
+
+    pub fn save(&self, ctx: &mut Context) -> GameResult<()> {
+        let file = ctx.filesystem.create(SAVEGAME_PATH)?;
+        serde_json::to_writer(file, self).map_err(io::Error::from)?;
+        Ok(())
+    }
+
+    /// Load the saved game, if there is one.
+    pub fn load(ctx: &mut Context) -> GameResult<Option<SavedGame>> {
+        if !ctx.filesystem.exists(SAVEGAME_PATH) {
+            return Ok(None);
+        }
+        let file = ctx.filesystem.open(SAVEGAME_PATH)?;
+        serde_json::from_reader(file).map(Some).map_err(|e| {
+            GameError::ResourceLoadError(format!("Failed to load {}: {}", SAVEGAME_PATH, e))
+        })
+    }
+
+    /// Throw away the saved game, e.g. once it's over.
+    pub fn delete(ctx: &mut Context) -> GameResult<()> {
+        if ctx.filesystem.exists(SAVEGAME_PATH) {
+            ctx.filesystem.delete(SAVEGAME_PATH)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use super::super::simulation::LEVEL_BANNER_TIME;
+
+    #[test]
+    fn test_save_round_trip() {
+        let mut sim = Simulation::new(
+            Difficulty::Hard,
+            GameConfig::default(),
+            seeded_rng(99),
+            640.0,
+            480.0,
+        );
+        sim.level_banner.update(LEVEL_BANNER_TIME);
+        sim.check_for_level_respawn();
+        sim.score = 1234;
+        sim.level = 3;
+        {
+            let mut transform = sim.system.borrow_mut::<Transform>(sim.player).unwrap();
+            transform.pos = Point2::new(-40.0, 25.0);
+            transform.facing = 1.5;
+        }
+        let rocks = sim.system.count_components::<Rock>();
+        let next_roll: u64 = sim.rng.clone().gen();
+
+        let json = serde_json::to_string(&SavedGame::capture(&sim, 42.0)).unwrap();
+        assert_eq!(sim.rng.gen::<u64>(), next_roll, "Saving used up a random number.");
+        let saved: SavedGame = serde_json::from_str(&json).unwrap();
+        let restored = saved.restore();
+
+        assert_eq!(restored.score, 1234);
+        assert_eq!(restored.level, 3);
+        assert_eq!(restored.difficulty, Difficulty::Hard);
+        assert_eq!(saved.elapsed, 42.0);
+        let transform = restored.system.get::<Transform>(restored.player).unwrap();
+        assert_eq!(transform.pos, Point2::new(-40.0, 25.0));
+        assert_eq!(transform.facing, 1.5);
+        assert_eq!(restored.system.count_components::<Rock>(), rocks);
+        assert!(!restored.level_banner.is_showing());
+
+        assert!(serde_json::from_str::<SavedGame>("{ not a save").is_err());
+    }
+}
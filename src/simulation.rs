@@ -0,0 +1,1284 @@
+use ggez::graphics;
+use ggez::{Context, GameResult};
+use rand::rngs::StdRng;
+
+use super::better_ecs::{Ecs, EntityId};
+use super::components::{
+    Blink, DamageFlash, Health, Invulnerable, Lifetime, Player, PointValue, Rock, ScorePopup,
+    Transform, Visible, Weapon,
+};
+use super::config::GameConfig;
+use super::difficulty::Difficulty;
+use super::prefabs::{
+    clear_escape_corridor, create_boss, create_player, create_rocks, create_score_popup,
+    rock_kill_trauma, spawn_from_edges, spawn_scattered, split_rock, SpawnPattern,
+};
+use super::profiler::Profiler;
+use super::systems::{
+    collision_system, lifetime_system, physics_system, player_system, regen_system,
+};
+use super::{Assets, InputState};
+
+/// **********************************************************************
+/// The `Simulation` is everything about the game that isn't drawing,
+/// sound, or the window: the world, the score, and the rules that move
+/// them forward. It never touches a `Context`, so it can be stepped
+/// headless, e.g. thousands of times in a unit test.
+/// **********************************************************************
+
+pub type Score = u32;
+
+// Seconds after a kill during which another kill extends the combo.
+pub const COMBO_WINDOW: f32 = 1.5;
+pub const MAX_COMBO_MULTIPLIER: Score = 5;
+
+/// Tracks kills made in quick succession. Each kill within
+/// `COMBO_WINDOW` seconds of the previous one bumps the score
+/// multiplier, up to `MAX_COMBO_MULTIPLIER`.
+#[derive(Debug, Clone)]
+pub struct Combo {
+    multiplier: Score,
+    timer: f32,
+}
+
+impl Default for Combo {
+    fn default() -> Self {
+        Combo {
+            multiplier: 1,
+            timer: 0.0,
+        }
+    }
+}
+
+/// How well the player shot, for the end-of-game summary.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct ShotStats {
+    pub shots_fired: u32,
+    pub rocks_destroyed: u32,
+}
+
+impl ShotStats {
+    /// Rocks destroyed per shot fired, as a percentage, or `None` before
+    /// the first shot. Bosses take several shots, so this can be low even
+    /// for a good shot, and never goes over 100.
+    pub fn accuracy(&self) -> Option<f32> {
+        if self.shots_fired == 0 {
+            return None;
+        }
+        let accuracy = 100.0 * self.rocks_destroyed as f32 / self.shots_fired as f32;
+        Some(accuracy.min(100.0))
+    }
+}
+
+impl Combo {
+    pub fn multiplier(&self) -> Score {
+        self.multiplier
+    }
+
+    /// Record a kill worth `base_points`, returning the points actually
+    /// awarded.
+    pub fn register_kill(&mut self, base_points: Score) -> Score {
+        if self.timer > 0.0 {
+            self.multiplier = (self.multiplier + 1).min(MAX_COMBO_MULTIPLIER);
+        } else {
+            self.multiplier = 1;
+        }
+        self.timer = COMBO_WINDOW;
+
+        base_points * self.multiplier
+    }
+
+    /// Count down the combo window. Returns true if the multiplier just
+    /// dropped back to 1.
+    pub fn update(&mut self, dt: f32) -> bool {
+        if self.timer <= 0.0 {
+            return false;
+        }
+
+        self.timer -= dt;
+        if self.timer <= 0.0 && self.multiplier != 1 {
+            self.multiplier = 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn reset(&mut self) {
+        *self = Combo::default();
+    }
+}
+
+/// The base points for destroying `entity`, before any combo multiplier.
+/// Entities without a `PointValue` are worth nothing.
+pub fn kill_points(system: &Ecs, entity: EntityId) -> Score {
+    system
+        .borrow::<PointValue>(entity)
+        .map(|value| value.points)
+        .unwrap_or(0)
+}
+
+// Seconds the "LEVEL N" banner is shown before a wave spawns.
+pub const LEVEL_BANNER_TIME: f32 = 1.5;
+
+/// The banner announcing a new level. While it's showing, the level's
+/// wave of rocks is held back; it spawns once the banner finishes.
+pub struct LevelBanner {
+    timer: f32,
+    wave_pending: bool,
+    text: Option<graphics::Text>,
+}
+
+impl Default for LevelBanner {
+    fn default() -> Self {
+        LevelBanner {
+            timer: 0.0,
+            wave_pending: false,
+            text: None,
+        }
+    }
+}
+
+impl LevelBanner {
+    /// Show the banner for a new level.
+    pub fn start(&mut self) {
+        self.timer = LEVEL_BANNER_TIME;
+        self.wave_pending = true;
+        self.text = None;
+    }
+
+    pub fn update(&mut self, dt: f32) {
+        if self.timer > 0.0 {
+            self.timer -= dt;
+        }
+    }
+
+    pub fn is_showing(&self) -> bool {
+        self.timer > 0.0
+    }
+
+    /// Whether the level's wave has yet to spawn.
+    pub fn is_wave_pending(&self) -> bool {
+        self.wave_pending
+    }
+
+    /// Returns true exactly once after the banner finishes, when it's
+    /// time to spawn the level's wave.
+    pub fn take_wave(&mut self) -> bool {
+        if !self.is_showing() && self.wave_pending {
+            self.wave_pending = false;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Build the banner's text, if it hasn't been built for this level yet.
+    pub fn build_text(&mut self, ctx: &mut Context, assets: &Assets, level: i32) -> GameResult<()> {
+        if self.is_showing() && self.text.is_none() {
+            let text_str = format!("LEVEL {}", level);
+            self.text = Some(graphics::Text::new(ctx, &text_str, &assets.font)?);
+        }
+        Ok(())
+    }
+
+    pub fn draw(&self, ctx: &mut Context, screen_width: u32, screen_height: u32) -> GameResult<()> {
+        match &self.text {
+            Some(text) if self.is_showing() => {
+                let dest = graphics::Point2::new(
+                    (screen_width as f32 - text.width() as f32) / 2.0,
+                    (screen_height as f32 - text.height() as f32) / 2.0,
+                );
+                graphics::draw(ctx, text, dest, 0.0)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+// Camera trauma when the player is hit.
+pub const PLAYER_HIT_TRAUMA: f32 = 0.8;
+
+/// Seconds on a level before extra rocks start arriving to hurry the
+/// player along, and the seconds between each one after that.
+pub const PRESSURE_TIME: f32 = 30.0;
+pub const PRESSURE_INTERVAL: f32 = 10.0;
+
+// Every this many levels, the wave comes with a boss.
+pub const BOSS_LEVEL_INTERVAL: i32 = 3;
+
+/// Whether the world is running. Anything but `Playing` freezes it.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum GameState {
+    Playing,
+    Paused,
+    /// Waiting for the player to confirm they want to quit.
+    ConfirmQuit,
+    /// The player is dead. The world stays up to be drawn behind the
+    /// final stats, but nothing moves.
+    GameOver,
+}
+
+pub struct Simulation {
+    pub player: EntityId,
+    pub level: i32,
+    pub score: Score,
+    pub combo: Combo,
+    pub level_banner: LevelBanner,
+    pub difficulty: Difficulty,
+    pub config: GameConfig,
+    pub rng: StdRng,
+
+    pub state: GameState,
+    /// Set when the pause came from the window losing focus, rather
+    /// than from the player.
+    pub auto_paused: bool,
+
+    /// The size of the playing field, in world units.
+    pub width: f32,
+    pub height: f32,
+
+    /// Set whenever something shown in the HUD changes.
+    pub gui_dirty: bool,
+
+    /// Camera shake built up this step, for whoever is drawing the game.
+    pub trauma: f32,
+
+    /// Seconds spent on the current level's wave.
+    pub level_time: f32,
+
+    /// Rocks shot down this game, splits and bosses included.
+    pub rocks_destroyed: u32,
+
+    /// Times each system in `step`, when turned on.
+    pub profiler: Profiler,
+
+    pub system: Ecs,
+}
+
+impl Simulation {
+    pub fn new(
+        difficulty: Difficulty,
+        config: GameConfig,
+        rng: StdRng,
+        width: f32,
+        height: f32,
+    ) -> Simulation {
+        let mut system = Ecs::new();
+        let player = create_player(&mut system, difficulty.player_shot_time(&config));
+        system.borrow_mut::<Player>(player).unwrap().weapon = Weapon::new(config.weapon);
+
+        // The first wave spawns once its banner finishes.
+        let mut level_banner = LevelBanner::default();
+        level_banner.start();
+
+        Simulation {
+            player,
+            level: 0,
+            score: 0,
+            combo: Combo::default(),
+            level_banner,
+            difficulty,
+            config,
+            rng,
+            state: GameState::Playing,
+            auto_paused: false,
+            width,
+            height,
+            gui_dirty: true,
+            trauma: 0.0,
+            level_time: 0.0,
+            rocks_destroyed: 0,
+            profiler: Profiler::default(),
+            system,
+        }
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.state == GameState::Playing
+    }
+
+    /// Pause or unpause at the player's request.
+    pub fn toggle_pause(&mut self) {
+        self.state = match self.state {
+            GameState::Playing => GameState::Paused,
+            GameState::Paused => GameState::Playing,
+            GameState::ConfirmQuit | GameState::GameOver => return,
+        };
+        self.auto_paused = false;
+    }
+
+    /// Stop the game and ask the player whether they really want to quit.
+    /// Once the game is over there's nothing to lose, so this does nothing.
+    pub fn request_quit(&mut self) {
+        if self.state == GameState::GameOver {
+            return;
+        }
+        self.state = GameState::ConfirmQuit;
+        self.auto_paused = false;
+    }
+
+    /// Answer the quit prompt. Returns true if the game should quit now;
+    /// otherwise play resumes. Does nothing if there's no prompt up.
+    pub fn answer_quit(&mut self, quit: bool) -> bool {
+        if self.state != GameState::ConfirmQuit {
+            return false;
+        }
+        if !quit {
+            self.state = GameState::Playing;
+        }
+        quit
+    }
+
+    /// Pause when the window loses focus, and resume when it gets focus
+    /// back, unless the player paused by hand in the meantime.
+    pub fn focus_changed(&mut self, gained: bool) {
+        if gained {
+            if self.auto_paused {
+                self.state = GameState::Playing;
+                self.auto_paused = false;
+            }
+        } else if self.state == GameState::Playing {
+            self.state = GameState::Paused;
+            self.auto_paused = true;
+        }
+    }
+
+    /// Advance the game by `dt` seconds. Sounds are only played if
+    /// `assets` are given. Does nothing unless the game is `Playing`.
+    pub fn step(&mut self, dt: f32, input: &InputState, assets: Option<&Assets>) {
+        if self.state != GameState::Playing {
+            return;
+        }
+
+        // Update the player state based on the user input.
+        let start = self.profiler.start();
+        player_system(&mut self.system, input, &self.config, assets, dt);
+        self.profiler.stop("input", start);
+
+        let start = self.profiler.start();
+        physics_system(&self.system, &self.config, dt, self.width, self.height);
+        self.profiler.stop("physics", start);
+
+        let start = self.profiler.start();
+        self.update_timers(dt);
+        self.profiler.stop("timers", start);
+
+        // Handle the results of things moving:
+        // collision detection, object death, and if
+        // we have killed all the rocks in the level,
+        // spawn more of them.
+        let start = self.profiler.start();
+        let field = self.config.wrap_mode.collision_field(self.width, self.height);
+        collision_system(&mut self.system, field, assets);
+        self.profiler.stop("collision", start);
+
+        let start = self.profiler.start();
+        let was_alive = !self.is_game_over();
+        self.clear_dead_stuff();
+
+        self.apply_pressure(dt);
+        self.check_for_level_respawn();
+        self.profiler.stop("cleanup", start);
+
+        if self.is_game_over() {
+            self.combo.reset();
+            if was_alive {
+                self.trauma += PLAYER_HIT_TRAUMA;
+            }
+            self.state = GameState::GameOver;
+        }
+    }
+
+    /// Run every countdown in the game: entity lifetimes, score popups,
+    /// spawn protection, damage flashes, health regeneration, the combo
+    /// window, and the level banner. They all live here so they all see
+    /// the same (possibly scaled, possibly zero) `dt`, and so none of them
+    /// can tick while `step` is holding the world still.
+    fn update_timers(&mut self, dt: f32) {
+        // Expired entities are despawned by `clear_dead_stuff`.
+        lifetime_system(&self.system, dt);
+
+        // Move the score popups.
+        self.system
+            .components_mut::<ScorePopup>()
+            .for_each(|(_, mut popup)| {
+                popup.update_popup(&self.system, dt);
+            });
+
+        self.update_invulnerability(dt);
+        self.update_damage_flashes(dt);
+        regen_system(&self.system, dt);
+
+        if self.combo.update(dt) {
+            self.gui_dirty = true;
+        }
+        self.level_banner.update(dt);
+    }
+
+    /// Count down spawn protection, blinking the entity meanwhile. When it
+    /// runs out, the blink that goes with it is removed too.
+    fn update_invulnerability(&mut self, dt: f32) {
+        let mut blinked = Vec::new();
+        for (id, mut blink) in self.system.components_mut::<Blink>() {
+            let was_visible = blink.is_visible();
+            blink.update(dt);
+            if blink.is_visible() != was_visible {
+                blinked.push((self.system.get_parent(id).unwrap(), blink.is_visible()));
+            }
+        }
+        for (entity, visible) in blinked {
+            if visible {
+                let _ = self.system.unset::<Visible>(entity);
+            } else {
+                self.system.insert(entity, Visible(false)).unwrap();
+            }
+        }
+        let mut expired = Vec::new();
+        for (id, mut invulnerable) in self.system.components_mut::<Invulnerable>() {
+            invulnerable.update(dt);
+            if invulnerable.is_over() {
+                expired.push(self.system.get_parent(id).unwrap());
+            }
+        }
+        for entity in expired {
+            let _ = self.system.unset::<Invulnerable>(entity);
+            // Only undo the blink's own hiding; anything hidden for some
+            // other reason stays hidden.
+            if let Ok(blink) = self.system.unset::<Blink>(entity) {
+                if !blink.is_visible() {
+                    let _ = self.system.unset::<Visible>(entity);
+                }
+            }
+        }
+    }
+
+    /// Fade out hit flashes, removing them once they're done.
+    fn update_damage_flashes(&mut self, dt: f32) {
+        let mut faded = Vec::new();
+        for (id, mut flash) in self.system.components_mut::<DamageFlash>() {
+            flash.update(dt);
+            if flash.is_over() {
+                faded.push(self.system.get_parent(id).unwrap());
+            }
+        }
+        for entity in faded {
+            let _ = self.system.unset::<DamageFlash>(entity);
+        }
+    }
+
+    /// Shots fired and rocks destroyed so far this game.
+    pub fn shot_stats(&self) -> ShotStats {
+        let shots_fired = self
+            .system
+            .try_borrow::<Player>(self.player)
+            .map_or(0, |player| player.shots_fired);
+        ShotStats {
+            shots_fired,
+            rocks_destroyed: self.rocks_destroyed,
+        }
+    }
+
+    /// The camera trauma built up by hits since the last call.
+    pub fn take_trauma(&mut self) -> f32 {
+        std::mem::replace(&mut self.trauma, 0.0)
+    }
+
+    /// The game is over once the player dies. A player entity that's gone
+    /// altogether counts as dead, rather than bringing the game down.
+    pub fn is_game_over(&self) -> bool {
+        self.system
+            .try_borrow::<Health>(self.player)
+            .map_or(true, |health| health.health <= 0.0)
+    }
+
+    pub fn clear_dead_stuff(&mut self) {
+        // In `ComponentId` order, since the order decides the combo
+        // scoring and the rng draws for splitting, and replays need both.
+        let removals = self
+            .system
+            .components_sorted::<Health>()
+            .filter(|&id| self.system.borrow_by_id::<Health>(id).unwrap().health <= 0.0)
+            .map(|id| self.system.get_parent(id).unwrap())
+            .filter(|&entity| entity != self.player)
+            .collect::<Vec<_>>();
+
+        // Award points for everything that died, with a popup where it was.
+        for &id in &removals {
+            let base_points = kill_points(&self.system, id);
+            if base_points == 0 {
+                continue;
+            }
+
+            let points = self.combo.register_kill(base_points);
+            self.score += points;
+            self.gui_dirty = true;
+
+            let pos = self.system.borrow::<Transform>(id).unwrap().pos;
+            // Out of ids, there's just no popup.
+            let _ = create_score_popup(&mut self.system, pos, points);
+        }
+
+        // Dead rocks break apart, so remember where they were.
+        let dead_rocks = removals
+            .iter()
+            .filter_map(|&id| {
+                let rock = self.system.try_borrow::<Rock>(id)?;
+                let transform = self.system.try_borrow::<Transform>(id)?;
+                Some((transform.pos, rock.size))
+            }).collect::<Vec<_>>();
+
+        for id in removals {
+            self.system.remove_entity(id).unwrap();
+        }
+        self.system.despawn_if::<Lifetime, _>(Lifetime::is_over);
+
+        self.rocks_destroyed += dead_rocks.len() as u32;
+        for (pos, size) in dead_rocks {
+            self.trauma += rock_kill_trauma(size);
+            split_rock(
+                &mut self.system,
+                &mut self.rng,
+                pos,
+                size,
+                self.difficulty.max_rock_vel(&self.config),
+            );
+        }
+    }
+
+    /// Once a wave has dragged on for `pressure_time`, send in another rock
+    /// every `pressure_interval`, as far from the player as the field allows.
+    fn apply_pressure(&mut self, dt: f32) {
+        if self.level_banner.is_showing() || self.level_cleared() {
+            return;
+        }
+        let start = self.config.pressure_time;
+        let interval = self.config.pressure_interval;
+        // How many extra rocks are due by time `t`.
+        let due = |t: f32| if t < start { 0 } else { ((t - start) / interval) as i32 + 1 };
+        let before = self.level_time;
+        self.level_time += dt;
+        let extra = due(self.level_time) - due(before);
+        if extra <= 0 {
+            return;
+        }
+
+        let player_pos = match self.system.try_borrow::<Transform>(self.player) {
+            Some(transform) => transform.pos,
+            None => return,
+        };
+        let min_radius = self.config.rock_spawn_max_radius;
+        create_rocks(
+            &mut self.system,
+            &mut self.rng,
+            extra,
+            (self.width, self.height),
+            player_pos,
+            min_radius,
+            self.width.max(self.height).max(min_radius + 1.0),
+            self.difficulty.max_rock_vel(&self.config),
+        );
+    }
+
+    /// The level is over once every rock is gone. A destroyed rock only
+    /// splits when it's removed, so one that is dying but still around
+    /// keeps the level going until its fragments exist. Popups and other
+    /// non-gameplay entities don't count.
+    pub fn level_cleared(&self) -> bool {
+        !self.system.has_any::<Rock>()
+    }
+
+    /// Spawn the next wave once its banner is done, or start the next
+    /// level once this one is cleared. Waits while the player is missing,
+    /// since waves are placed around the player.
+    pub fn check_for_level_respawn(&mut self) {
+        let transform = match self.system.try_get::<Transform>(self.player) {
+            Some(transform) => transform,
+            None => return,
+        };
+        if self.level_banner.take_wave() {
+            if self.level > 0 && self.level % BOSS_LEVEL_INTERVAL == 0 {
+                // If the Ecs is out of ids, the level goes without a boss.
+                let _ = create_boss(
+                    &mut self.system,
+                    &mut self.rng,
+                    (self.width, self.height),
+                    transform.pos,
+                    150.0,
+                    250.0,
+                    self.difficulty.max_rock_vel(&self.config) * 0.5,
+                );
+            }
+
+            let num = self.difficulty.rocks_for_level(&self.config, self.level);
+            let max_vel = self.difficulty.max_rock_vel(&self.config);
+            match self.config.spawn_pattern {
+                SpawnPattern::Ring => {
+                    let rocks = create_rocks(
+                        &mut self.system,
+                        &mut self.rng,
+                        num,
+                        (self.width, self.height),
+                        transform.pos,
+                        self.config.rock_spawn_min_radius,
+                        self.config.rock_spawn_max_radius,
+                        max_vel,
+                    );
+                    // Leave the player a way out.
+                    clear_escape_corridor(
+                        &self.system,
+                        &mut self.rng,
+                        &rocks,
+                        (self.width, self.height),
+                        transform.pos,
+                        transform.facing,
+                        self.config.escape_angle,
+                        self.config.rock_spawn_min_radius,
+                        self.config.rock_spawn_max_radius,
+                        max_vel,
+                    );
+                }
+                SpawnPattern::Edges => {
+                    spawn_from_edges(
+                        &mut self.system,
+                        &mut self.rng,
+                        num,
+                        (self.width, self.height),
+                        max_vel,
+                    );
+                }
+                SpawnPattern::Scattered => {
+                    spawn_scattered(
+                        &mut self.system,
+                        &mut self.rng,
+                        num,
+                        (self.width, self.height),
+                        transform.pos,
+                        self.config.rock_spawn_min_radius,
+                        max_vel,
+                    );
+                }
+            }
+        } else if !self.level_banner.is_showing() && self.level_cleared() {
+            self.level += 1;
+            self.level_time = 0.0;
+            self.gui_dirty = true;
+            self.level_banner.start();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand::SeedableRng;
+
+    use ggez::graphics::Point2;
+    use ggez::nalgebra as na;
+
+    use super::super::components::{
+        Physics, RockSize, Shot, Sprite, BOSS_ABSORB_HEAL, DAMAGE_FLASH_TIME,
+    };
+    use super::super::prefabs::{
+        create_rock, create_shot, rock_points, BOSS_LIFE, MAX_ROCKS, ROCK_SPLIT_COUNT,
+        POPUP_LIFE, SCATTER_SPACING, SHOT_DAMAGE, SPAWN_PROTECTION_TIME,
+    };
+    use super::super::vec::wrapped_distance;
+
+    const DT: f32 = 1.0 / 60.0;
+
+    fn headless_sim() -> Simulation {
+        Simulation::new(
+            Difficulty::Normal,
+            GameConfig::default(),
+            StdRng::from_seed([7; 32]),
+            640.0,
+            480.0,
+        )
+    }
+
+    const BASE_POINTS: Score = 20;
+
+    #[test]
+    fn test_quick_kills_beat_slow_kills() {
+        let mut quick = Combo::default();
+        let quick_score = quick.register_kill(BASE_POINTS) + quick.register_kill(BASE_POINTS);
+
+        let mut slow = Combo::default();
+        let mut slow_score = slow.register_kill(BASE_POINTS);
+        slow.update(COMBO_WINDOW + 0.1);
+        slow_score += slow.register_kill(BASE_POINTS);
+
+        assert!(quick_score > slow_score);
+        assert_eq!(slow.multiplier(), 1);
+    }
+
+    #[test]
+    fn test_combo_multiplier_is_capped() {
+        let mut combo = Combo::default();
+        for _ in 0..10 {
+            combo.register_kill(BASE_POINTS);
+        }
+
+        assert_eq!(combo.multiplier(), MAX_COMBO_MULTIPLIER);
+    }
+
+    #[test]
+    fn test_level_banner_counts_down() {
+        let mut banner = LevelBanner::default();
+        assert!(!banner.is_showing());
+
+        banner.start();
+        let dt = 0.25;
+        let steps = (LEVEL_BANNER_TIME / dt) as i32;
+        for _ in 0..steps - 1 {
+            banner.update(dt);
+            assert!(banner.is_showing());
+            assert!(!banner.take_wave(), "Wave spawned before the banner finished.");
+        }
+        banner.update(dt);
+
+        assert!(!banner.is_showing());
+        assert!(banner.take_wave());
+        assert!(!banner.take_wave(), "Wave spawned twice.");
+    }
+
+    #[test]
+    fn test_wave_waits_for_level_banner() {
+        let mut sim = headless_sim();
+        sim.level_banner = LevelBanner::default();
+
+        sim.step(DT, &InputState::default(), None);
+        assert_eq!(sim.level, 1);
+        assert!(sim.level_banner.is_showing());
+
+        let mut steps = 0;
+        while sim.level_banner.is_showing() {
+            assert!(sim.level_cleared(), "Rocks spawned before the banner finished.");
+            sim.step(DT, &InputState::default(), None);
+            steps += 1;
+        }
+        // Give or take a step for rounding.
+        let expected = (LEVEL_BANNER_TIME / DT).round() as i32;
+        assert!((steps - expected).abs() <= 1, "The banner took {} steps.", steps);
+        assert!(!sim.level_cleared(), "The wave didn't spawn when the banner finished.");
+    }
+
+    #[test]
+    fn test_dying_rock_holds_off_next_level() {
+        let mut sim = headless_sim();
+        sim.level_banner = LevelBanner::default();
+        let rock = create_rock(&mut sim.system, RockSize::Large).unwrap();
+        sim.system.borrow_mut::<Health>(rock).unwrap().health = 0.0;
+        create_score_popup(&mut sim.system, Point2::origin(), 20).unwrap();
+
+        sim.check_for_level_respawn();
+        assert!(!sim.level_cleared());
+        assert_eq!(sim.level, 0);
+
+        sim.system.remove_entity(rock).unwrap();
+        sim.check_for_level_respawn();
+        assert_eq!(sim.level, 1, "Leftover popups held off the next level.");
+    }
+
+    #[test]
+    fn test_first_wave_size_comes_from_config() {
+        let config = GameConfig {
+            start_rocks: 2,
+            ..GameConfig::default()
+        };
+        let mut sim = Simulation::new(
+            Difficulty::Normal,
+            config,
+            StdRng::from_seed([7; 32]),
+            640.0,
+            480.0,
+        );
+
+        sim.level_banner.update(LEVEL_BANNER_TIME);
+        sim.check_for_level_respawn();
+
+        assert_eq!(sim.system.count_components::<Rock>(), 2);
+    }
+
+    #[test]
+    fn test_pressure_spawns_rocks_until_cleared() {
+        let mut sim = headless_sim();
+        sim.level_banner = LevelBanner::default();
+        let rock = create_rock(&mut sim.system, RockSize::Large).unwrap();
+        sim.system.borrow_mut::<Transform>(rock).unwrap().pos = Point2::new(300.0, 200.0);
+        sim.level_time = PRESSURE_TIME - DT / 2.0;
+
+        sim.step(DT, &InputState::default(), None);
+        assert_eq!(sim.system.count_components::<Rock>(), 2);
+
+        for rock in sim.system.entities_with::<Rock>() {
+            sim.system.remove_entity(rock).unwrap();
+        }
+        sim.step(DT, &InputState::default(), None);
+        assert_eq!(sim.level, 1);
+        assert_eq!(sim.level_time, 0.0);
+    }
+
+    #[test]
+    fn test_next_wave_waits_and_spawns_around_player() {
+        let mut sim = Simulation::new(
+            Difficulty::Normal,
+            GameConfig::default(),
+            StdRng::from_seed([7; 32]),
+            2000.0,
+            2000.0,
+        );
+        sim.level_banner = LevelBanner::default();
+
+        sim.step(DT, &InputState::default(), None);
+        assert_eq!(sim.level, 1);
+        assert!(sim.level_banner.is_showing());
+
+        // The player moves away while the banner is up.
+        let player_pos = Point2::new(300.0, 200.0);
+        sim.system.borrow_mut::<Transform>(sim.player).unwrap().pos = player_pos;
+        while sim.level_banner.is_showing() {
+            assert!(sim.level_cleared(), "Rocks spawned during the banner.");
+            sim.step(DT, &InputState::default(), None);
+        }
+
+        assert!(!sim.level_cleared());
+        let min_radius = sim.config.rock_spawn_min_radius;
+        for (_, _, transform) in sim.system.components2_ref::<Rock, Transform>() {
+            assert!(na::distance(&transform.pos, &player_pos) >= min_radius);
+        }
+    }
+
+    #[test]
+    fn test_scattered_wave_keeps_clear_of_player() {
+        let toml = "spawn_pattern = \"scattered\"\nrock_spawn_min_radius = 200.0";
+        let config = GameConfig::from_toml(toml).unwrap();
+        let mut sim =
+            Simulation::new(Difficulty::Normal, config, StdRng::from_seed([7; 32]), 640.0, 480.0);
+        let player_pos = Point2::new(100.0, -50.0);
+        sim.system.borrow_mut::<Transform>(sim.player).unwrap().pos = player_pos;
+        while sim.level_banner.is_showing() || sim.level_cleared() {
+            sim.step(DT, &InputState::default(), None);
+        }
+
+        let min_radius = sim.config.rock_spawn_min_radius;
+        assert!(min_radius > SCATTER_SPACING);
+        for (_, _, transform) in sim.system.components2_ref::<Rock, Transform>() {
+            let distance = wrapped_distance(transform.pos, player_pos, sim.width, sim.height);
+            assert!(distance >= min_radius, "A rock spawned {} from the player.", distance);
+        }
+    }
+
+    fn rock_at(system: &mut Ecs, x: f32, y: f32) -> EntityId {
+        let rock = create_rock(system, RockSize::Large).unwrap();
+        system.borrow_mut::<Transform>(rock).unwrap().pos = Point2::new(x, y);
+        rock
+    }
+
+    #[test]
+    fn test_spawn_blink_leaves_other_hiding_alone() {
+        let mut sim = headless_sim();
+        let hidden = rock_at(&mut sim.system, 200.0, 200.0);
+        sim.system.set(hidden, Invulnerable::new(SPAWN_PROTECTION_TIME)).unwrap();
+        sim.system.set(hidden, Visible(false)).unwrap();
+        let sprite_of = |sim: &Simulation, entity| sim.system.get::<Sprite>(entity).unwrap();
+
+        let mut player_blinked = false;
+        while sim.system.has_component::<Invulnerable>(sim.player).unwrap().is_some() {
+            sim.step(DT, &InputState::default(), None);
+            player_blinked |= !sprite_of(&sim, sim.player).is_visible(&sim.system);
+        }
+
+        assert!(player_blinked, "The player never blinked.");
+        assert!(sprite_of(&sim, sim.player).is_visible(&sim.system));
+        assert!(!sprite_of(&sim, hidden).is_visible(&sim.system));
+    }
+
+    #[test]
+    fn test_missing_player_ends_game() {
+        let mut sim = headless_sim();
+        sim.level_banner.update(LEVEL_BANNER_TIME);
+        sim.system.remove_entity(sim.player).unwrap();
+
+        sim.step(DT, &InputState::default(), None);
+
+        assert_eq!(sim.state, GameState::GameOver);
+        assert!(sim.level_cleared(), "A wave spawned with no player.");
+    }
+
+    #[test]
+    fn test_wraps_at_playfield_edge() {
+        // A field wider than the 640 pixel window.
+        let mut sim = Simulation::new(
+            Difficulty::Normal,
+            GameConfig::default(),
+            StdRng::from_seed([7; 32]),
+            2000.0,
+            480.0,
+        );
+        let player = sim.player;
+        sim.system.borrow_mut::<Physics>(player).unwrap().velocity.x = 60.0;
+
+        sim.system.borrow_mut::<Transform>(player).unwrap().pos.x = 330.0;
+        sim.step(DT, &InputState::default(), None);
+        assert!(sim.system.get::<Transform>(player).unwrap().pos.x > 330.0);
+
+        sim.system.borrow_mut::<Transform>(player).unwrap().pos.x = 999.9;
+        sim.step(DT, &InputState::default(), None);
+        assert!(sim.system.get::<Transform>(player).unwrap().pos.x < -999.0);
+    }
+
+    #[test]
+    fn test_shot_between_rocks_hits_the_older_one() {
+        for _ in 0..10 {
+            let mut sim = headless_sim();
+            sim.level_banner = LevelBanner::default();
+            let pos = Point2::new(200.0, 0.0);
+            let older = create_rock(&mut sim.system, RockSize::Small).unwrap();
+            let newer = create_rock(&mut sim.system, RockSize::Small).unwrap();
+            for &rock in [older, newer].iter() {
+                sim.system.borrow_mut::<Transform>(rock).unwrap().pos = pos;
+            }
+            let shot = create_shot(&mut sim.system).unwrap();
+            sim.system.borrow_mut::<Transform>(shot).unwrap().pos = pos;
+
+            sim.step(DT, &InputState::default(), None);
+
+            assert!(!sim.system.has_entity(older));
+            assert!(sim.system.has_entity(newer), "One shot killed two rocks.");
+        }
+    }
+
+    #[test]
+    fn test_shot_stats() {
+        let mut sim = headless_sim();
+        let player = sim.player;
+        let config = sim.config.clone();
+        sim.system.scoped(|system, commands| {
+            let mut player = system.borrow_mut::<Player>(player).unwrap();
+            for _ in 0..3 {
+                player.fire_player_shot(system, commands, &config, None, 0.0);
+            }
+        });
+        for _ in 0..2 {
+            let rock = create_rock(&mut sim.system, RockSize::Small).unwrap();
+            sim.system.borrow_mut::<Transform>(rock).unwrap().pos = Point2::new(200.0, 0.0);
+            sim.system.borrow_mut::<Health>(rock).unwrap().health = 0.0;
+        }
+
+        sim.step(DT, &InputState::default(), None);
+
+        let stats = sim.shot_stats();
+        assert_eq!(stats.shots_fired, 3);
+        assert_eq!(stats.rocks_destroyed, 2);
+        assert!((stats.accuracy().unwrap() - 200.0 / 3.0).abs() < 1e-4);
+        assert_eq!(ShotStats::default().accuracy(), None);
+    }
+
+    #[test]
+    fn test_lifetime_despawns_at_zero() {
+        let mut sim = headless_sim();
+        let dt = 0.25;
+        let entity = sim.system.create_entity();
+        sim.system.set(entity, Lifetime::new(1.0)).unwrap();
+
+        for _ in 0..3 {
+            sim.step(dt, &InputState::default(), None);
+            assert!(sim.system.has_entity(entity), "Despawned early.");
+        }
+        sim.step(dt, &InputState::default(), None);
+        assert!(!sim.system.has_entity(entity));
+    }
+
+    #[test]
+    fn test_kill_points() {
+        let mut ecs = Ecs::new();
+        let worthless = ecs.create_entity();
+        let valuable = ecs.create_entity();
+        ecs.set(valuable, PointValue::new(100)).unwrap();
+
+        assert_eq!(kill_points(&ecs, worthless), 0);
+        assert_eq!(kill_points(&ecs, valuable), 100);
+    }
+
+    #[test]
+    fn test_shooting_a_rock_scores_its_points() {
+        for &size in [RockSize::Small, RockSize::Large].iter() {
+            let mut sim = headless_sim();
+            let rock = create_rock(&mut sim.system, size).unwrap();
+            let pos = Point2::new(200.0, 0.0);
+            sim.system.borrow_mut::<Transform>(rock).unwrap().pos = pos;
+            let shot = create_shot(&mut sim.system).unwrap();
+            sim.system.borrow_mut::<Transform>(shot).unwrap().pos = pos;
+
+            sim.step(DT, &InputState::default(), None);
+
+            assert!(!sim.system.has_entity(rock));
+            assert_eq!(sim.score, rock_points(size));
+        }
+        assert_eq!(rock_points(RockSize::Small), 100);
+        assert_eq!(rock_points(RockSize::Large), 20);
+    }
+
+    #[test]
+    fn test_player_survives_empty_field() {
+        let mut sim = headless_sim();
+        let mut input = InputState {
+            xaxis: 1.0,
+            yaxis: 1.0,
+            ..InputState::default()
+        };
+
+        for step in 0..600 {
+            for rock in sim.system.entities_with::<Rock>() {
+                sim.system.remove_entity(rock).unwrap();
+            }
+            input.fire = step % 10 < 5;
+            input.begin_frame();
+            sim.step(DT, &input, None);
+            assert!(!sim.is_game_over(), "Player died in an empty field.");
+        }
+    }
+
+    #[test]
+    fn test_rock_cap_holds() {
+        let mut sim = headless_sim();
+        let mut input = InputState {
+            xaxis: -1.0,
+            ..InputState::default()
+        };
+
+        for step in 0..600 {
+            input.fire = step % 10 < 5;
+            input.begin_frame();
+            sim.step(DT, &input, None);
+            assert!(sim.system.count_components::<Rock>() <= MAX_ROCKS);
+        }
+    }
+
+    #[test]
+    fn test_boss_takes_several_shots() {
+        let mut sim = headless_sim();
+        let boss_pos = Point2::new(200.0, 0.0);
+        let boss = create_rock(&mut sim.system, RockSize::Boss).unwrap();
+        sim.system.borrow_mut::<Transform>(boss).unwrap().pos = boss_pos;
+
+        let shots_to_kill = (BOSS_LIFE / SHOT_DAMAGE).ceil() as i32;
+        for shots in 1..=shots_to_kill {
+            let shot = create_shot(&mut sim.system).unwrap();
+            sim.system.borrow_mut::<Transform>(shot).unwrap().pos = boss_pos;
+            sim.step(DT, &InputState::default(), None);
+
+            if shots < shots_to_kill {
+                let health: Health = sim.system.get(boss).unwrap();
+                assert_eq!(health.health, BOSS_LIFE - shots as f32 * SHOT_DAMAGE);
+                assert_eq!(sim.system.count_components::<Rock>(), 1, "Boss split early.");
+            }
+        }
+
+        assert!(!sim.system.has_entity(boss));
+        let fragments = sim.system.entities_with::<Rock>();
+        assert_eq!(fragments.len(), ROCK_SPLIT_COUNT);
+        for fragment in fragments {
+            assert_eq!(sim.system.borrow::<Rock>(fragment).unwrap().size, RockSize::Large);
+        }
+    }
+
+    #[test]
+    fn test_boss_absorbs_rocks_up_to_max_health() {
+        let mut sim = headless_sim();
+        sim.level_banner = LevelBanner::default();
+        let boss_pos = Point2::new(200.0, 0.0);
+        let boss = create_rock(&mut sim.system, RockSize::Boss).unwrap();
+        sim.system.borrow_mut::<Transform>(boss).unwrap().pos = boss_pos;
+        sim.system.borrow_mut::<Health>(boss).unwrap().health = BOSS_LIFE - 0.5;
+
+        for _ in 0..2 {
+            let rock = create_rock(&mut sim.system, RockSize::Small).unwrap();
+            sim.system.borrow_mut::<Transform>(rock).unwrap().pos = boss_pos;
+            sim.step(DT, &InputState::default(), None);
+
+            assert!(!sim.system.has_entity(rock), "Rock wasn't swallowed.");
+            assert_eq!(sim.system.get::<Health>(boss).unwrap().health, BOSS_LIFE);
+        }
+        assert_eq!(sim.score, 0);
+        assert_eq!(sim.system.count_components::<Rock>(), 1);
+    }
+
+    #[test]
+    fn test_two_bosses_cant_share_a_rock() {
+        let mut sim = headless_sim();
+        sim.level_banner = LevelBanner::default();
+        let pos = Point2::new(200.0, 0.0);
+        let mut bosses = Vec::new();
+        for _ in 0..2 {
+            let boss = create_rock(&mut sim.system, RockSize::Boss).unwrap();
+            sim.system.borrow_mut::<Transform>(boss).unwrap().pos = pos;
+            sim.system.borrow_mut::<Health>(boss).unwrap().health = 1.0;
+            bosses.push(boss);
+        }
+        let rock = create_rock(&mut sim.system, RockSize::Small).unwrap();
+        sim.system.borrow_mut::<Transform>(rock).unwrap().pos = pos;
+
+        sim.step(DT, &InputState::default(), None);
+
+        assert!(!sim.system.has_entity(rock));
+        let total: f32 =
+            bosses.iter().map(|&boss| sim.system.get::<Health>(boss).unwrap().health).sum();
+        assert_eq!(total, 2.0 + BOSS_ABSORB_HEAL, "The rock healed both bosses.");
+        assert_eq!(sim.score, 0);
+    }
+
+    #[test]
+    fn test_surviving_a_hit_flashes() {
+        let mut sim = headless_sim();
+        let player = sim.player;
+        sim.system.unset::<Invulnerable>(player).unwrap();
+        sim.system.insert(player, Health::new(3.0)).unwrap();
+        let player_pos = sim.system.get::<Transform>(player).unwrap().pos;
+        rock_at(&mut sim.system, player_pos.x, player_pos.y);
+
+        sim.step(DT, &InputState::default(), None);
+        assert!(sim.system.borrow::<Health>(player).unwrap().health > 0.0);
+        assert!(sim.system.has_component::<DamageFlash>(player).unwrap().is_some());
+
+        for _ in 0..(DAMAGE_FLASH_TIME / DT) as i32 + 1 {
+            sim.step(DT, &InputState::default(), None);
+        }
+        assert_eq!(sim.system.has_component::<DamageFlash>(player).unwrap(), None);
+    }
+
+    #[test]
+    fn test_focus_loss_auto_pauses() {
+        let mut sim = headless_sim();
+
+        sim.focus_changed(false);
+        assert_eq!(sim.state, GameState::Paused);
+        assert!(sim.auto_paused);
+
+        sim.focus_changed(true);
+        assert!(sim.is_playing());
+        assert!(!sim.auto_paused);
+
+        // A manual pause survives the window coming back into focus.
+        sim.toggle_pause();
+        sim.focus_changed(false);
+        sim.focus_changed(true);
+        assert_eq!(sim.state, GameState::Paused);
+    }
+
+    #[test]
+    fn test_quit_needs_confirmation() {
+        let mut sim = headless_sim();
+        assert!(!sim.answer_quit(true), "Quit without a prompt.");
+
+        sim.request_quit();
+        assert_eq!(sim.state, GameState::ConfirmQuit);
+        assert!(!sim.answer_quit(false));
+        assert!(sim.is_playing());
+
+        sim.request_quit();
+        assert!(sim.answer_quit(true));
+    }
+
+    #[test]
+    fn test_two_shots_score_one_rock_once() {
+        let mut sim = headless_sim();
+        let rock_pos = Point2::new(200.0, 0.0);
+        let rock = create_rock(&mut sim.system, RockSize::Small).unwrap();
+        sim.system.borrow_mut::<Transform>(rock).unwrap().pos = rock_pos;
+        for _ in 0..2 {
+            let shot = create_shot(&mut sim.system).unwrap();
+            sim.system.borrow_mut::<Transform>(shot).unwrap().pos = rock_pos;
+        }
+
+        sim.step(DT, &InputState::default(), None);
+
+        assert!(!sim.system.has_entity(rock));
+        assert_eq!(sim.score, rock_points(RockSize::Small));
+        assert_eq!(sim.system.count_components::<Shot>(), 1, "Both shots were used up.");
+    }
+
+    #[test]
+    fn test_kill_popup_shows_points_then_despawns() {
+        let mut sim = headless_sim();
+        let rock = rock_at(&mut sim.system, 200.0, 0.0);
+        let shot = create_shot(&mut sim.system).unwrap();
+        sim.system.borrow_mut::<Transform>(shot).unwrap().pos = Point2::new(200.0, 0.0);
+
+        sim.step(DT, &InputState::default(), None);
+        assert!(!sim.system.has_entity(rock));
+        let popups: Vec<Score> =
+            sim.system.components_ref::<ScorePopup>().map(|(_, popup)| popup.points).collect();
+        assert_eq!(popups, vec![rock_points(RockSize::Large)]);
+
+        // A step to spare for rounding.
+        let steps = (POPUP_LIFE / DT).ceil() as usize + 1;
+        for _ in 0..steps {
+            sim.step(DT, &InputState::default(), None);
+        }
+        assert!(!sim.system.has_any::<ScorePopup>(), "The popup outlived its lifetime.");
+    }
+
+    #[test]
+    fn test_game_over_freezes_world() {
+        let mut sim = headless_sim();
+        let rock = create_rock(&mut sim.system, RockSize::Large).unwrap();
+        sim.system.borrow_mut::<Transform>(rock).unwrap().pos = Point2::new(200.0, 0.0);
+        sim.system.borrow_mut::<Physics>(rock).unwrap().velocity.x = 50.0;
+        sim.system.borrow_mut::<Health>(sim.player).unwrap().health = 0.0;
+
+        sim.step(DT, &InputState::default(), None);
+        assert_eq!(sim.state, GameState::GameOver);
+
+        let frozen_pos = sim.system.get::<Transform>(rock).unwrap().pos;
+        for _ in 0..10 {
+            sim.step(DT, &InputState::default(), None);
+        }
+
+        assert!(sim.system.has_entity(rock));
+        assert!(sim.system.has_entity(sim.player));
+        assert_eq!(sim.system.get::<Transform>(rock).unwrap().pos, frozen_pos);
+    }
+
+    #[test]
+    fn test_physics_skips_stale_transform() {
+        let mut sim = headless_sim();
+        let ghost = sim
+            .system
+            .build_entity()
+            .with(Transform::default())
+            .with1(Physics::new)
+            .build()
+            .unwrap();
+        let physics: Physics = sim.system.get(ghost).unwrap();
+        assert!(physics.transform.is_valid(&sim.system));
+
+        sim.system.unset::<Transform>(ghost).unwrap();
+        assert!(!physics.transform.is_valid(&sim.system));
+
+        // Would panic if the physics system tried to move the ghost.
+        sim.step(DT, &InputState::default(), None);
+    }
+
+    #[test]
+    fn test_shot_lifetime_frozen_while_paused() {
+        let mut sim = headless_sim();
+        let shot = create_shot(&mut sim.system).unwrap();
+        let lifetime = |sim: &Simulation| sim.system.get::<Lifetime>(shot).unwrap().remaining;
+        let start = lifetime(&sim);
+
+        sim.toggle_pause();
+        sim.step(DT, &InputState::default(), None);
+        assert_eq!(lifetime(&sim), start);
+
+        // A time scale of zero holds it still too.
+        sim.toggle_pause();
+        sim.step(0.0, &InputState::default(), None);
+        assert_eq!(lifetime(&sim), start);
+
+        sim.step(DT, &InputState::default(), None);
+        assert_eq!(lifetime(&sim), start - DT);
+    }
+}
@@ -0,0 +1,159 @@
+use super::better_ecs::Ecs;
+use super::components::{
+    Collider, Health, Lifetime, Physics, Player, Regen, Sprite, CHARGE_SPRITE_GROWTH,
+};
+use super::config::GameConfig;
+use super::{Assets, InputState};
+
+/// *********************************************************************
+/// The systems that make up one step of the game, as free functions over
+/// the `Ecs`. Each takes only the data it needs, so each can be run on
+/// its own, e.g. in a unit test. `Simulation::step` calls them in order
+/// and adds the game rules (scoring, levels) around them.
+/// **********************************************************************
+
+/// Turn and thrust the player ships, and fire their guns. Shots are
+/// spawned once every player has been handled.
+pub fn player_system(
+    ecs: &mut Ecs,
+    input: &InputState,
+    config: &GameConfig,
+    assets: Option<&Assets>,
+    dt: f32,
+) {
+    ecs.scoped(|system, commands| {
+        system
+            .components_mut::<Player>()
+            .for_each(|(id, mut player)| {
+                player.player_handle_input(system, input, config, dt);
+                player.try_fire(system, commands, input, config, assets, dt);
+
+                // The ship swells as the shot charges.
+                let entity = system.get_parent(id).unwrap();
+                let mut sprite = system.borrow_mut::<Sprite>(entity).unwrap();
+                sprite.scale = 1.0 + CHARGE_SPRITE_GROWTH * player.charge_fraction();
+            });
+    });
+}
+
+/// Move everything with `Physics`, then keep it on a field of size `sx`
+/// by `sy` according to `config.wrap_mode`. Anything whose transform has
+/// gone away is skipped.
+pub fn physics_system(ecs: &Ecs, config: &GameConfig, dt: f32, sx: f32, sy: f32) {
+    ecs.components_mut::<Physics>()
+        .filter(|(_, component)| component.transform.is_valid(ecs))
+        .for_each(|(_, mut component)| {
+            component.update_actor_position(ecs, config, dt);
+            component.wrap_actor_position(ecs, sx, sy, config.wrap_mode)
+        });
+}
+
+/// Count down every `Lifetime`. Whatever runs out is left for the
+/// simulation's cleanup to despawn.
+pub fn lifetime_system(ecs: &Ecs, dt: f32) {
+    ecs.components_mut::<Lifetime>()
+        .for_each(|(_, mut lifetime)| {
+            lifetime.update(dt);
+        });
+}
+
+/// Heal everything with both `Regen` and `Health`.
+pub fn regen_system(ecs: &Ecs, dt: f32) {
+    for (id, regen) in ecs.components_ref::<Regen>() {
+        let entity = ecs.get_parent(id).unwrap();
+        if let Ok(mut health) = ecs.borrow_mut::<Health>(entity) {
+            regen.apply(&mut health, dt);
+        }
+    }
+}
+
+/// Check every `Collider` against the player and the shots, on a field of
+/// size `field` (see `WrapMode::collision_field`).
+///
+/// Colliders go in `ComponentId` order, so when one shot touches two
+/// rocks, the older rock takes the hit, the same way every run. That
+/// keeps replays in step.
+pub fn collision_system(ecs: &mut Ecs, field: (f32, f32), assets: Option<&Assets>) {
+    ecs.scoped(|system, commands| {
+        for id in system.components_sorted::<Collider>() {
+            let collider = system.borrow_by_id::<Collider>(id).unwrap();
+            collider.check_for_collisions(system, commands, field, assets);
+        }
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ggez::graphics::{Point2, Vector2};
+
+    use super::super::components::Transform;
+
+    #[test]
+    fn test_physics_system_moves_by_velocity() {
+        let mut ecs = Ecs::new();
+        let entity = ecs
+            .build_entity()
+            .with(Transform::default())
+            .with1(Physics::new)
+            .build()
+            .unwrap();
+        ecs.borrow_mut::<Physics>(entity).unwrap().velocity = Vector2::new(10.0, -4.0);
+
+        physics_system(&ecs, &GameConfig::default(), 0.5, 640.0, 480.0);
+        assert_eq!(ecs.get::<Transform>(entity).unwrap().pos, Point2::new(5.0, -2.0));
+
+        physics_system(&ecs, &GameConfig::default(), 0.5, 640.0, 480.0);
+        assert_eq!(ecs.get::<Transform>(entity).unwrap().pos, Point2::new(10.0, -4.0));
+    }
+
+    #[test]
+    fn test_physics_follows_target_fps() {
+        let config = GameConfig::from_toml("target_fps = 30").unwrap();
+        let dt = config.timestep();
+        assert_eq!(dt, 1.0 / 30.0);
+
+        let mut ecs = Ecs::new();
+        let entity = ecs
+            .build_entity()
+            .with(Transform::default())
+            .with1(Physics::new)
+            .build()
+            .unwrap();
+        ecs.borrow_mut::<Physics>(entity).unwrap().velocity = Vector2::new(30.0, 0.0);
+        for _ in 0..config.target_fps() {
+            physics_system(&ecs, &config, dt, 640.0, 480.0);
+        }
+
+        let pos = ecs.get::<Transform>(entity).unwrap().pos;
+        assert!((pos.x - 30.0).abs() < 1e-3);
+        assert_eq!(pos.y, 0.0);
+    }
+
+    #[test]
+    fn test_regen_heals_up_to_max() {
+        let mut ecs = Ecs::new();
+        let regen = Regen { rate: 1.0, max: 2.5 };
+        let entity = ecs.create_entity();
+        ecs.set(entity, Health::new(3.0)).unwrap();
+        ecs.set(entity, regen.clone()).unwrap();
+        ecs.borrow_mut::<Health>(entity).unwrap().health = 1.0;
+        let bystander = ecs.create_entity();
+        ecs.set(bystander, regen.clone()).unwrap();
+        let frail = ecs.create_entity();
+        ecs.set(frail, Health::new(2.0)).unwrap();
+        ecs.set(frail, regen).unwrap();
+        ecs.borrow_mut::<Health>(frail).unwrap().health = 1.0;
+
+        regen_system(&ecs, 0.5);
+        assert_eq!(ecs.get::<Health>(entity).unwrap().health, 1.5);
+        regen_system(&ecs, 0.5);
+        assert_eq!(ecs.get::<Health>(entity).unwrap().health, 2.0);
+        for _ in 0..4 {
+            regen_system(&ecs, 0.5);
+        }
+        assert_eq!(ecs.get::<Health>(entity).unwrap().health, 2.5);
+        assert_eq!(ecs.get::<Health>(frail).unwrap().health, 2.0, "Healed past max health.");
+        assert!(ecs.get::<Health>(bystander).is_err());
+    }
+}
@@ -1,3 +1,5 @@
+use rand::rngs::StdRng;
+use rand::SeedableRng;
 use std::cell::RefCell;
 use std::mem;
 
@@ -54,3 +56,13 @@ impl<T> RefCellTryReplaceExt<T> for RefCell<T> {
         }
     }
 }
+
+///////////////////////////////////////////////////////////////////////////////////
+// rand 0.5 has no `seed_from_u64`, so this spreads a u64 over a full seed.
+pub fn seeded_rng(seed: u64) -> StdRng {
+    let mut bytes = [0; 32];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = (seed >> ((i % 8) * 8)) as u8;
+    }
+    StdRng::from_seed(bytes)
+}
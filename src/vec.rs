@@ -1,4 +1,5 @@
-use ggez::graphics::Vector2;
+use ggez::graphics::{Point2, Vector2};
+use rand::Rng;
 
 /// *********************************************************************
 /// Basic stuff, make some helpers for vector functions.
@@ -14,9 +15,89 @@ pub fn vec_from_angle(angle: f32) -> Vector2 {
     Vector2::new(vx, vy)
 }
 
-/// Just makes a random `Vector2` with the given max magnitude.
-pub fn random_vec(max_magnitude: f32) -> Vector2 {
-    let angle = rand::random::<f32>() * 2.0 * std::f32::consts::PI;
-    let mag = rand::random::<f32>() * max_magnitude;
+/// A random `Vector2` pointing any which way, with a magnitude between
+/// `min_magnitude` and `max_magnitude`.
+pub fn random_vec_in<R: Rng>(rng: &mut R, min_magnitude: f32, max_magnitude: f32) -> Vector2 {
+    let angle = rng.gen::<f32>() * 2.0 * std::f32::consts::PI;
+    let mag = min_magnitude + rng.gen::<f32>() * (max_magnitude - min_magnitude);
     vec_from_angle(angle) * (mag)
 }
+
+/// Turn `current` toward `target`, both in radians, by at most
+/// `max_step`. It goes whichever way round is shorter, and stops on
+/// `target` rather than overshooting it.
+pub fn rotate_toward(current: f32, target: f32, max_step: f32) -> f32 {
+    use std::f32::consts::PI;
+    let diff = (target - current + PI).rem_euclid(2.0 * PI) - PI;
+    if diff.abs() <= max_step {
+        target
+    } else {
+        current + max_step * diff.signum()
+    }
+}
+
+/// Bounce `v` off a surface with the given unit `normal`.
+pub fn reflect(v: Vector2, normal: Vector2) -> Vector2 {
+    v - normal * (2.0 * v.dot(&normal))
+}
+
+/// The shortest vector from `b` to `a` on a playing field of size `sx`
+/// by `sy` that wraps around at the edges, so points just either side
+/// of a seam are close together.
+pub fn wrapped_delta(a: Point2, b: Point2, sx: f32, sy: f32) -> Vector2 {
+    // Each axis wraps on its own, so the shortest way round is just the
+    // shortest way along each axis.
+    let shortest = |d: f32, size: f32| {
+        [d - size, d, d + size]
+            .iter()
+            .cloned()
+            .fold(d, |best, x| if x.abs() < best.abs() { x } else { best })
+    };
+    let delta = a - b;
+    Vector2::new(shortest(delta.x, sx), shortest(delta.y, sy))
+}
+
+/// The distance between two points on a wrapping playing field; the
+/// length of `wrapped_delta`.
+pub fn wrapped_distance(a: Point2, b: Point2, sx: f32, sy: f32) -> f32 {
+    wrapped_delta(a, b, sx, sy).norm()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_random_vec_in_stays_in_range() {
+        let mut rng = StdRng::from_seed([7; 32]);
+        for _ in 0..100 {
+            let mag = random_vec_in(&mut rng, 10.0, 20.0).norm();
+            assert!(mag >= 10.0 - 1e-4 && mag <= 20.0 + 1e-4, "Magnitude {} out of range.", mag);
+        }
+    }
+
+    #[test]
+    fn test_wrapped_distance_crosses_the_seam() {
+        let left = Point2::new(-315.0, 0.0);
+        let right = Point2::new(315.0, 0.0);
+
+        assert!((wrapped_distance(left, right, 640.0, 480.0) - 10.0).abs() < 1e-4);
+        assert!((wrapped_distance(left, right, 1000.0, 480.0) - 370.0).abs() < 1e-4);
+        let corner = Point2::new(315.0, 235.0);
+        let opposite = Point2::new(-315.0, -235.0);
+        assert!((wrapped_distance(corner, opposite, 640.0, 480.0) - 200f32.sqrt()).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_rotate_toward_takes_short_way_without_overshoot() {
+        // 3.0 to -3.0 is a short hop across PI, not most of a turn back.
+        let turned = rotate_toward(3.0, -3.0, 0.1);
+        assert!((turned - 3.1).abs() < 1e-5, "Turned to {}.", turned);
+        assert!((rotate_toward(-3.0, 3.0, 0.1) + 3.1).abs() < 1e-5);
+
+        assert_eq!(rotate_toward(1.0, 1.05, 0.1), 1.05);
+        assert_eq!(rotate_toward(1.0, 0.5, 0.1), 0.9);
+    }
+}